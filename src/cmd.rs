@@ -1,54 +1,127 @@
 use std::path::PathBuf;
 
-use clap::{arg, command, ArgAction, Parser, ValueHint};
-
-// TODO: add ability to add custom special keywords
-// TODO: include dotfiles
-// TODO: strict mode: fail if couldn't stow/unstow/adopt/restow any package
+use clap::{ArgAction, Parser, ValueHint};
+use rustow::{Config, ConflictPolicy, LinkMode, StowerBuilder};
 
 #[derive(Parser)]
 #[command(author, version)]
 pub struct Args {
     /// The directory containing the packages to be stowed.
+    /// Defaults to the current directory.
     #[arg(
         short = 'd',
         long,
         value_name = "DIR",
         value_hint = ValueHint::DirPath,
-        default_value = ".",
     )]
-    pub stow_dir: PathBuf,
+    pub stow_dir: Option<PathBuf>,
 
     /// The directory in which the packages will be stowed.
+    /// Defaults to the parent of the stow directory.
     #[arg(
         short = 't',
         long,
         value_name = "DIR",
         value_hint = ValueHint::DirPath,
-        default_value = "..",
     )]
-    pub target_dir: PathBuf,
+    pub target_dir: Option<PathBuf>,
 
     /// Enable verbose output.
-    #[arg(short, long, default_value = "false")]
+    #[arg(short, long, default_value = "false", overrides_with = "no_verbose")]
     pub verbose: bool,
 
+    /// Disable verbose output, even if the config file enables it.
+    #[arg(long, default_value = "false")]
+    pub no_verbose: bool,
+
     /// Enable simulation mode.
     #[arg(short, long, default_value = "false")]
     pub simulate: bool,
 
-    /// Disable the special paths feature.
+    /// Do not change anything if any package cannot be fully
+    /// stowed/unstowed/restowed/adopted.
+    #[arg(long, default_value = "false", overrides_with = "no_strict")]
+    pub strict: bool,
+
+    /// Disable strict mode, even if the config file enables it.
     #[arg(long, default_value = "false")]
+    pub no_strict: bool,
+
+    /// What to do when a path is in the way: 'abort' (default) changes nothing,
+    /// 'skip' leaves the path as it is and changes the others.
+    #[arg(long, value_name = "POLICY")]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    /// Disable the special paths feature.
+    #[arg(long, default_value = "false", overrides_with = "special_paths")]
     pub no_special_paths: bool,
 
+    /// Enable the special paths feature, even if the config file disables it.
+    #[arg(long, default_value = "false")]
+    pub special_paths: bool,
+
+    /// Symlink files into the target directory, even if the config file sets
+    /// another mode.
+    #[arg(long, default_value = "false", overrides_with_all = ["copy", "hardlink"])]
+    pub symlink: bool,
+
+    /// Copy files into the target directory instead of symlinking them.
+    #[arg(long, default_value = "false", overrides_with_all = ["symlink", "hardlink"])]
+    pub copy: bool,
+
+    /// Hard link files into the target directory instead of symlinking them.
+    /// The stow and target directories must be on the same filesystem.
+    #[arg(long, default_value = "false", overrides_with_all = ["symlink", "copy"])]
+    pub hardlink: bool,
+
+    /// Create symlinks with absolute paths instead of relative ones.
+    #[arg(long, default_value = "false", overrides_with = "relative")]
+    pub absolute: bool,
+
+    /// Create symlinks with relative paths, even if the config file sets
+    /// absolute ones.
+    #[arg(long, default_value = "false")]
+    pub relative: bool,
+
+    /// Link files ending with .tmpl instead of rendering them.
+    #[arg(long, default_value = "false", overrides_with = "templates")]
+    pub no_templates: bool,
+
+    /// Render files ending with .tmpl, even if the config file disables it.
+    #[arg(long, default_value = "false")]
+    pub templates: bool,
+
     /// Disable the security checks.
     #[arg(
         long,
         default_value = "false",
-        default_value_if("no-special-keywords", "true", "true")
+        default_value_if("no-special-keywords", "true", "true"),
+        overrides_with = "security_check"
     )]
     pub no_security_check: bool,
 
+    /// Enable the security checks, even if the config file disables them.
+    #[arg(long, default_value = "false")]
+    pub security_check: bool,
+
+    /// Allow packages whose names start with '.'.
+    /// Version control directories like '.git' are never packages.
+    #[arg(long, default_value = "false", overrides_with = "no_include_hidden")]
+    pub include_hidden: bool,
+
+    /// Skip packages whose names start with '.', even if the config file
+    /// allows them.
+    #[arg(long, default_value = "false")]
+    pub no_include_hidden: bool,
+
+    /// Map the 'dot-' prefix of file names in packages to '.'.
+    #[arg(long, default_value = "false", overrides_with = "no_dotfiles")]
+    pub dotfiles: bool,
+
+    /// Keep the 'dot-' prefix of file names, even if the config file maps it.
+    #[arg(long, default_value = "false")]
+    pub no_dotfiles: bool,
+
     /// Replace <FIND> with <REPLACE> in all paths.
     /// <FIND> uses regex to match paths but <REPLACE> does not.
     /// There can be only one replace argument.
@@ -60,6 +133,25 @@ pub struct Args {
      )]
     pub replace_name: Vec<String>,
 
+    /// Do not stow the files matching <REGEX>.
+    /// If <REGEX> contains '/', it is matched against the path from the package
+    /// root, otherwise against the file name.
+    #[arg(
+        long,
+        value_name = "REGEX",
+        action = ArgAction::Append,
+    )]
+    pub ignore: Vec<String>,
+
+    /// Only handle packages that have TAG in their manifest. Can be given
+    /// more than once to handle packages with any of the tags.
+    #[arg(
+        long,
+        value_name = "TAG",
+        action = ArgAction::Append,
+    )]
+    pub tag: Vec<String>,
+
     /// Stow the package.
     /// Creates symlinks of files in the package to target directory
     #[arg(
@@ -69,7 +161,7 @@ pub struct Args {
         num_args = 1..,
         action = ArgAction::Append,
         value_hint = ValueHint::FilePath,
-        required_unless_present_any = ["unstow", "restow", "adopt"],
+        required_unless_present_any = ["unstow", "restow", "adopt", "status"],
         next_line_help = true,
     )]
     pub stow: Vec<PathBuf>,
@@ -83,7 +175,7 @@ pub struct Args {
         num_args = 1..,
         action = ArgAction::Append,
         value_hint = ValueHint::FilePath,
-        required_unless_present_any = ["stow", "restow", "adopt"],
+        required_unless_present_any = ["stow", "restow", "adopt", "status"],
         next_line_help = true,
     )]
     pub unstow: Vec<PathBuf>,
@@ -97,7 +189,7 @@ pub struct Args {
         num_args = 1..,
         action = ArgAction::Append,
         value_hint = ValueHint::FilePath,
-        required_unless_present_any = ["stow", "unstow", "adopt"],
+        required_unless_present_any = ["stow", "unstow", "adopt", "status"],
         next_line_help = true,
     )]
     pub restow: Vec<PathBuf>,
@@ -112,8 +204,106 @@ pub struct Args {
         num_args = 1..,
         action = ArgAction::Append,
         value_hint = ValueHint::FilePath,
-        required_unless_present_any = ["stow", "unstow", "restow"],
+        required_unless_present_any = ["stow", "unstow", "restow", "status"],
         next_line_help = true,
     )]
     pub adopt: Vec<PathBuf>,
+
+    /// Show the status of the package.
+    /// Reports whether the package is stowed without changing anything
+    #[arg(
+        long,
+        value_name = "PACKAGE",
+        num_args = 1..,
+        action = ArgAction::Append,
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["stow", "unstow", "restow", "adopt"],
+        next_line_help = true,
+    )]
+    pub status: Vec<PathBuf>,
+
+    /// Print the status as JSON.
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "status",
+        conflicts_with_all = ["stow", "unstow", "restow", "adopt"],
+    )]
+    pub json: bool,
 }
+
+impl Args {
+    /// Builder with the settings in `config`, overridden by the arguments.
+    pub fn into_builder(self, config: Config) -> StowerBuilder {
+        let mut builder = StowerBuilder::new()
+            .interactive(true)
+            .config(config)
+            .ignore(self.ignore)
+            .tags(self.tag)
+            .stow(self.stow)
+            .unstow(self.unstow)
+            .restow(self.restow)
+            .adopt(self.adopt)
+            .status(self.status);
+
+        if let Some(stow_dir) = self.stow_dir {
+            builder = builder.stow_dir(stow_dir);
+        }
+        if let Some(target_dir) = self.target_dir {
+            builder = builder.target_dir(target_dir);
+        }
+        if let Some(on_conflict) = self.on_conflict {
+            builder = builder.on_conflict(on_conflict);
+        }
+        if let Ok([find, replace]) = <[String; 2]>::try_from(self.replace_name) {
+            builder = builder.replace_name(find, replace);
+        }
+
+        // The config is kept for the options whose flags are not given
+        if let Some(verbose) = flag(self.verbose, self.no_verbose) {
+            builder = builder.verbose(verbose);
+        }
+        if self.simulate {
+            builder = builder.simulate(true);
+        }
+        if let Some(strict) = flag(self.strict, self.no_strict) {
+            builder = builder.strict(strict);
+        }
+        if let Some(special_paths) = flag(self.special_paths, self.no_special_paths) {
+            builder = builder.special_paths(special_paths);
+        }
+        if let Some(templates) = flag(self.templates, self.no_templates) {
+            builder = builder.templates(templates);
+        }
+        if self.symlink {
+            builder = builder.mode(LinkMode::Symlink);
+        }
+        if self.copy {
+            builder = builder.mode(LinkMode::Copy);
+        }
+        if self.hardlink {
+            builder = builder.mode(LinkMode::HardLink);
+        }
+        if let Some(absolute) = flag(self.absolute, self.relative) {
+            builder = builder.absolute_links(absolute);
+        }
+        if self.json {
+            builder = builder.json(true);
+        }
+        if let Some(security_check) = flag(self.security_check, self.no_security_check) {
+            builder = builder.security_check(security_check);
+        }
+        if let Some(dotfiles) = flag(self.dotfiles, self.no_dotfiles) {
+            builder = builder.dotfiles(dotfiles);
+        }
+        if let Some(include_hidden) = flag(self.include_hidden, self.no_include_hidden) {
+            builder = builder.include_hidden(include_hidden);
+        }
+
+        builder
+    }
+}
+
+/// Value of an option that has a flag to turn it on and one to turn it off.
+/// `None` if neither is given.
+fn flag(on: bool, off: bool) -> Option<bool> { (on || off).then_some(on) }
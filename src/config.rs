@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    filesystem::Filesystem,
+    keyword::{self, Keyword},
+    stower::{ConflictPolicy, LinkMode},
+};
+
+/// Name of the config file.
+pub const CONFIG_FILE: &str = "rustow.toml";
+
+/// Name of GNU Stow's config file, which is also read.
+pub const STOWRC_FILE: &str = ".stowrc";
+
+/// Settings read from config files. Everything is optional, unset settings
+/// are left to the command line or the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Relative paths are relative to the directory of the config file.
+    pub stow_dir:       Option<PathBuf>,
+    /// Relative paths are relative to the directory of the config file.
+    pub target_dir:     Option<PathBuf>,
+    pub verbose:        Option<bool>,
+    pub strict:         Option<bool>,
+    pub special_paths:  Option<bool>,
+    pub security_check: Option<bool>,
+    pub dotfiles:       Option<bool>,
+    pub include_hidden: Option<bool>,
+    pub templates:      Option<bool>,
+    pub mode:           Option<LinkMode>,
+    pub absolute_links: Option<bool>,
+    /// Regex to find in file names and its replacement.
+    pub replace_name:   Option<(String, String)>,
+    pub ignore:         Vec<String>,
+    pub on_conflict:    Option<ConflictPolicy>,
+    /// User-defined special keywords by name. The leading `@` is optional.
+    pub keywords:       HashMap<String, Keyword>,
+    /// Custom template variables by name.
+    pub variables:      HashMap<String, String>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rustow/rustow.toml`, or `~/.config/rustow/rustow.toml`
+    /// if `$XDG_CONFIG_HOME` is not set.
+    pub fn user_path() -> Option<PathBuf> {
+        let config_home = keyword::builtin("xdg-config")?.resolve().ok()?;
+
+        Some(config_home.join("rustow").join(CONFIG_FILE))
+    }
+
+    /// Reads and merges every config file. From the lowest priority to the
+    /// highest: `~/.stowrc`, the user config file, then `.stowrc` and
+    /// `rustow.toml` in the stow directory. The stow directory is `stow_dir`
+    /// if given, otherwise the one in the user config files, otherwise the
+    /// current directory.
+    pub fn discover<F: Filesystem>(fs: &F, stow_dir: Option<&Path>) -> Result<Self> {
+        let home_stowrc = keyword::expand("~")
+            .ok()
+            .map(|home| PathBuf::from(home).join(STOWRC_FILE));
+
+        let mut config = Self::default();
+        for path in [home_stowrc, Self::user_path()].into_iter().flatten() {
+            config = config.merge(Self::load(fs, &path)?);
+        }
+
+        let stow_dir = stow_dir
+            .map(Path::to_path_buf)
+            .or_else(|| config.stow_dir.clone())
+            .unwrap_or_else(|| PathBuf::from("."));
+        for name in [STOWRC_FILE, CONFIG_FILE] {
+            let path = stow_dir.join(name);
+
+            // The stow directory cannot move itself
+            let local = Self::load(fs, &path)?;
+            config = config.merge(Self { stow_dir: None, ..local });
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the config file at `path`. Files named `.stowrc` are read as GNU
+    /// Stow options, the others as TOML. A missing file is an empty config.
+    pub fn load<F: Filesystem>(fs: &F, path: &Path) -> Result<Self> {
+        let contents = match fs.read(path) {
+            Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(why) => return Err(Error::io(path, path, why)),
+        };
+
+        let invalid = |error: String| {
+            Error::InvalidConfig {
+                source: path.to_path_buf(),
+                target: path.to_path_buf(),
+                error,
+            }
+        };
+
+        let config = if path.file_name() == Some(STOWRC_FILE.as_ref()) {
+            Self::parse_stowrc(&contents).map_err(invalid)?
+        } else {
+            toml::from_str::<Self>(&contents).map_err(|why| invalid(why.to_string()))?
+        };
+
+        // Built-in keywords are used first, so these would never have an effect
+        if let Some(name) = config
+            .keywords
+            .keys()
+            .find(|name| keyword::is_builtin(name.strip_prefix('@').unwrap_or(name)))
+        {
+            return Err(invalid(format!(
+                "keyword {name} is built in and cannot be redefined"
+            )));
+        }
+
+        // Paths are relative to the config file
+        let base = path.parent().unwrap_or(Path::new("."));
+        let resolve = |dir: Option<PathBuf>| -> Result<Option<PathBuf>> {
+            dir.map(|dir| {
+                keyword::expand(&dir.to_string_lossy())
+                    .map(|dir| base.join(dir))
+                    .map_err(|name| invalid(format!("environment variable {name} is not set")))
+            })
+            .transpose()
+        };
+
+        Ok(Self {
+            stow_dir: resolve(config.stow_dir)?,
+            target_dir: resolve(config.target_dir)?,
+            ..config
+        })
+    }
+
+    /// Settings of `self` overridden by the ones set in `other`. Ignore
+    /// patterns, keywords and variables of both are kept.
+    pub fn merge(mut self, other: Self) -> Self {
+        self.ignore.extend(other.ignore);
+        self.keywords.extend(other.keywords);
+        self.variables.extend(other.variables);
+
+        Self {
+            stow_dir:       other.stow_dir.or(self.stow_dir),
+            target_dir:     other.target_dir.or(self.target_dir),
+            verbose:        other.verbose.or(self.verbose),
+            strict:         other.strict.or(self.strict),
+            special_paths:  other.special_paths.or(self.special_paths),
+            security_check: other.security_check.or(self.security_check),
+            dotfiles:       other.dotfiles.or(self.dotfiles),
+            include_hidden: other.include_hidden.or(self.include_hidden),
+            templates:      other.templates.or(self.templates),
+            mode:           other.mode.or(self.mode),
+            absolute_links: other.absolute_links.or(self.absolute_links),
+            replace_name:   other.replace_name.or(self.replace_name),
+            ignore:         self.ignore,
+            on_conflict:    other.on_conflict.or(self.on_conflict),
+            keywords:       self.keywords,
+            variables:      self.variables,
+        }
+    }
+
+    /// Reads the options in a `.stowrc` file that Rustow supports. Options are
+    /// separated by whitespace and a word starting with `#` starts a comment,
+    /// so patterns like `--ignore=\#.*\#` are kept.
+    fn parse_stowrc(contents: &str) -> std::result::Result<Self, String> {
+        let mut config = Self::default();
+        let mut words = contents.lines().flat_map(|line| {
+            line.split_whitespace()
+                .take_while(|word| !word.starts_with('#'))
+        });
+
+        while let Some(word) = words.next() {
+            let (option, inline) = match word.split_once('=') {
+                Some((option, value)) => (option, Some(value.to_string())),
+                None => (word, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| words.next().map(str::to_string))
+                    .ok_or_else(|| format!("{option} needs a value"))
+            };
+
+            match option {
+                "-d" | "--dir" => config.stow_dir = Some(PathBuf::from(value()?)),
+                "-t" | "--target" => config.target_dir = Some(PathBuf::from(value()?)),
+                "--ignore" => config.ignore.push(value()?),
+                "--dotfiles" => config.dotfiles = Some(true),
+                "-v" | "--verbose" => config.verbose = Some(true),
+                // Warnings must not end up in the machine-readable output
+                _ => eprintln!("Option {option} in {STOWRC_FILE} is not supported. Ignoring..."),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFs;
+
+    #[test]
+    fn load_rejects_built_in_keywords() {
+        let fs = MemoryFs::new();
+
+        for name in ["@home", "root", "@env:EDITOR", "@xdg-config"] {
+            let path = Path::new("/").join(name).join(CONFIG_FILE);
+            fs.add_file(
+                &path,
+                format!("[keywords]\n\"{name}\" = \"/x\"\n"),
+            )
+            .unwrap();
+
+            assert!(
+                matches!(
+                    Config::load(&fs, &path),
+                    Err(Error::InvalidConfig { .. })
+                ),
+                "{name} is accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_stowrc_reads_supported_options() {
+        for (contents, stow_dir, ignore, dotfiles) in [
+            ("", None, vec![], None),
+            ("--dir=/dots", Some("/dots"), vec![], None),
+            (
+                "-d /dots --dotfiles",
+                Some("/dots"),
+                vec![],
+                Some(true),
+            ),
+            (
+                "--ignore=\\.cache\n--ignore \\.local",
+                None,
+                vec![r"\.cache", r"\.local"],
+                None,
+            ),
+            ("--ignore=\\#.*\\#", None, vec![r"\#.*\#"], None),
+            (
+                "# --dir=/dots\n--dotfiles # --dir=/dots",
+                None,
+                vec![],
+                Some(true),
+            ),
+            (
+                "--no-folding --dir=/dots",
+                Some("/dots"),
+                vec![],
+                None,
+            ),
+        ] {
+            let config = Config::parse_stowrc(contents).unwrap();
+
+            assert_eq!(
+                config.stow_dir.as_deref(),
+                stow_dir.map(Path::new),
+                "{contents}"
+            );
+            assert_eq!(config.ignore, ignore, "{contents}");
+            assert_eq!(config.dotfiles, dotfiles, "{contents}");
+        }
+
+        assert!(Config::parse_stowrc("--target").is_err());
+    }
+
+    #[test]
+    fn merge_overrides_settings_and_keeps_lists() {
+        let base = Config {
+            stow_dir: Some(PathBuf::from("/dots")),
+            verbose: Some(true),
+            strict: Some(true),
+            ignore: vec!["a".to_string()],
+            variables: HashMap::from([
+                ("name".to_string(), "base".to_string()),
+                ("os".to_string(), "linux".to_string()),
+            ]),
+            ..Config::default()
+        };
+        let other = Config {
+            strict: Some(false),
+            ignore: vec!["b".to_string()],
+            variables: HashMap::from([("name".to_string(), "other".to_string())]),
+            ..Config::default()
+        };
+
+        let config = base.merge(other);
+
+        assert_eq!(config.stow_dir, Some(PathBuf::from("/dots")));
+        assert_eq!(config.verbose, Some(true));
+        assert_eq!(config.strict, Some(false));
+        assert_eq!(config.ignore, ["a", "b"]);
+        assert_eq!(config.variables["name"], "other");
+        assert_eq!(config.variables["os"], "linux");
+    }
+}
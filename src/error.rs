@@ -0,0 +1,218 @@
+use std::{
+    error,
+    fmt,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Everything that can go wrong while stowing. `source` is the path in the
+/// package and `target` is the matching path in the target directory.
+#[derive(Debug)]
+pub enum Error {
+    /// Something is in the way in the target directory.
+    Conflict {
+        source: PathBuf,
+        target: PathBuf,
+        reason: String,
+    },
+    /// The target is a symlink that does not point into the stow directory.
+    NotOwned { source: PathBuf, target: PathBuf },
+    /// A file under `@root` is not owned by root.
+    SecurityCheckFailed { source: PathBuf, target: PathBuf },
+    InvalidRegex {
+        source:  PathBuf,
+        target:  PathBuf,
+        pattern: String,
+        error:   regex::Error,
+    },
+    /// A file name that cannot be handled, e.g. `..` or the filesystem root.
+    InvalidName { source: PathBuf, target: PathBuf },
+    /// An environment variable needed for the path is not set.
+    MissingVariable {
+        source: PathBuf,
+        target: PathBuf,
+        name:   String,
+    },
+    /// A special keyword the manifest of the package does not allow.
+    KeywordNotAllowed {
+        source: PathBuf,
+        target: PathBuf,
+        name:   String,
+    },
+    /// A hook script exited with an error.
+    HookFailed {
+        source: PathBuf,
+        target: PathBuf,
+        reason: String,
+    },
+    /// A hard link cannot be created because the package and the target are
+    /// on different filesystems.
+    CrossDevice { source: PathBuf, target: PathBuf },
+    /// A template file that cannot be rendered.
+    InvalidTemplate {
+        source: PathBuf,
+        target: PathBuf,
+        error:  String,
+    },
+    /// A config file that cannot be parsed.
+    InvalidConfig {
+        source: PathBuf,
+        target: PathBuf,
+        error:  String,
+    },
+    PermissionDenied {
+        source: PathBuf,
+        target: PathBuf,
+        error:  io::Error,
+    },
+    Io {
+        source: PathBuf,
+        target: PathBuf,
+        error:  io::Error,
+    },
+}
+
+impl Error {
+    /// Wraps `error` with the paths it happened on. Permission errors get their
+    /// own variant.
+    pub fn io(source: &Path, target: &Path, error: io::Error) -> Self {
+        let source = source.to_path_buf();
+        let target = target.to_path_buf();
+
+        if error.kind() == io::ErrorKind::PermissionDenied {
+            Self::PermissionDenied { source, target, error }
+        } else {
+            Self::Io { source, target, error }
+        }
+    }
+
+    pub fn source_path(&self) -> &Path {
+        match self {
+            Self::Conflict { source, .. }
+            | Self::NotOwned { source, .. }
+            | Self::SecurityCheckFailed { source, .. }
+            | Self::InvalidRegex { source, .. }
+            | Self::InvalidName { source, .. }
+            | Self::MissingVariable { source, .. }
+            | Self::KeywordNotAllowed { source, .. }
+            | Self::HookFailed { source, .. }
+            | Self::CrossDevice { source, .. }
+            | Self::InvalidTemplate { source, .. }
+            | Self::InvalidConfig { source, .. }
+            | Self::PermissionDenied { source, .. }
+            | Self::Io { source, .. } => source,
+        }
+    }
+
+    pub fn target_path(&self) -> &Path {
+        match self {
+            Self::Conflict { target, .. }
+            | Self::NotOwned { target, .. }
+            | Self::SecurityCheckFailed { target, .. }
+            | Self::InvalidRegex { target, .. }
+            | Self::InvalidName { target, .. }
+            | Self::MissingVariable { target, .. }
+            | Self::KeywordNotAllowed { target, .. }
+            | Self::HookFailed { target, .. }
+            | Self::CrossDevice { target, .. }
+            | Self::InvalidTemplate { target, .. }
+            | Self::InvalidConfig { target, .. }
+            | Self::PermissionDenied { target, .. }
+            | Self::Io { target, .. } => target,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict { source, target, reason } =>
+                write!(
+                    f,
+                    "{} conflicts with {}: {reason}",
+                    target.display(),
+                    source.display()
+                ),
+            Self::NotOwned { source, target } =>
+                write!(
+                    f,
+                    "{} is not owned by the stow directory, refusing to touch it for {}",
+                    target.display(),
+                    source.display()
+                ),
+            Self::SecurityCheckFailed { source, target } =>
+                write!(
+                    f,
+                    "Security check failed for {}: everything in it must be owned by root to be \
+                 stowed to {}",
+                    source.display(),
+                    target.display()
+                ),
+            Self::InvalidRegex { source, pattern, error, .. } =>
+                write!(
+                    f,
+                    "Invalid regex \"{pattern}\" while handling {}: {error}",
+                    source.display()
+                ),
+            Self::InvalidName { source, .. } =>
+                write!(f, "Invalid file name: {}", source.display()),
+            Self::MissingVariable { source, name, .. } =>
+                write!(
+                    f,
+                    "Environment variable {name} is not set, cannot handle {}",
+                    source.display()
+                ),
+            Self::KeywordNotAllowed { source, name, .. } =>
+                write!(
+                    f,
+                    "Keyword {name} is not allowed by the package manifest, cannot handle {}",
+                    source.display()
+                ),
+            Self::HookFailed { source, reason, .. } =>
+                write!(f, "Hook {} failed: {reason}", source.display()),
+            Self::CrossDevice { source, target } =>
+                write!(
+                    f,
+                    "Cannot hard link {} to {}: they are on different filesystems",
+                    source.display(),
+                    target.display()
+                ),
+            Self::InvalidTemplate { source, error, .. } =>
+                write!(
+                    f,
+                    "Cannot render template {}: {error}",
+                    source.display()
+                ),
+            Self::InvalidConfig { source, error, .. } =>
+                write!(
+                    f,
+                    "Invalid config file {}: {error}",
+                    source.display()
+                ),
+            Self::PermissionDenied { source, target, error }
+            | Self::Io { source, target, error } =>
+                if source == target {
+                    write!(f, "{}: {error}", target.display())
+                } else {
+                    write!(
+                        f,
+                        "{} -> {}: {error}",
+                        target.display(),
+                        source.display()
+                    )
+                },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidRegex { error, .. } => Some(error),
+            Self::PermissionDenied { error, .. } | Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,549 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fs,
+    io::{self, Result},
+    os::unix::{self, fs::MetadataExt},
+    path::{Component, Path, PathBuf},
+};
+
+/// Maximum number of symlinks followed while resolving a path. Same limit as
+/// Linux uses.
+pub const MAX_SYMLINK_DEPTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub uid:       u32,
+    /// Device the entry is on.
+    pub dev:       u64,
+    pub ino:       u64,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool { self.file_type == FileType::Dir }
+
+    pub fn is_file(&self) -> bool { self.file_type == FileType::File }
+
+    pub fn is_symlink(&self) -> bool { self.file_type == FileType::Symlink }
+}
+
+/// Every filesystem operation [`Stower`](crate::stower::Stower) needs.
+///
+/// Relative symlink targets are resolved from the directory containing the
+/// link, like the OS does.
+pub trait Filesystem {
+    /// Metadata of `path` without following the symlink at the end.
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata>;
+
+    /// Metadata of `path` after following all the symlinks.
+    fn metadata(&self, path: &Path) -> Result<Metadata>;
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf>;
+
+    /// Contents of the file at `path`.
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+    /// Full paths of the entries in the directory.
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
+
+    fn create_symlink(&self, original: &Path, destination: &Path) -> Result<()>;
+
+    /// Creates the directory and all of its missing parents.
+    fn create_dir(&self, path: &Path) -> Result<()>;
+
+    /// Removes an empty directory.
+    fn remove_dir(&self, path: &Path) -> Result<()>;
+
+    /// Removes the directory with everything inside it.
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Removes a file or a symlink.
+    fn remove_file(&self, path: &Path) -> Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Creates `destination` as a hard link of the file `original`.
+    fn hard_link(&self, original: &Path, destination: &Path) -> Result<()>;
+
+    /// Writes `contents` to the file at `path`, replacing it if it exists.
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()>;
+
+    fn exists(&self, path: &Path) -> bool { self.metadata(path).is_ok() }
+
+    fn is_dir(&self, path: &Path) -> bool { self.metadata(path).is_ok_and(|m| m.is_dir()) }
+
+    fn is_file(&self, path: &Path) -> bool { self.metadata(path).is_ok_and(|m| m.is_file()) }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.symlink_metadata(path).is_ok_and(|m| m.is_symlink())
+    }
+}
+
+/// Lets a filesystem be given to a [`Stower`](crate::stower::Stower) and
+/// still be inspected after the run.
+impl<F: Filesystem + ?Sized> Filesystem for &F {
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> { (**self).symlink_metadata(path) }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> { (**self).metadata(path) }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> { (**self).read_link(path) }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> { (**self).read(path) }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> { (**self).read_dir(path) }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> { (**self).canonicalize(path) }
+
+    fn create_symlink(&self, original: &Path, destination: &Path) -> Result<()> {
+        (**self).create_symlink(original, destination)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> { (**self).create_dir(path) }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> { (**self).remove_dir(path) }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> { (**self).remove_dir_all(path) }
+
+    fn remove_file(&self, path: &Path) -> Result<()> { (**self).remove_file(path) }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> { (**self).rename(from, to) }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> { (**self).copy(from, to) }
+
+    fn hard_link(&self, original: &Path, destination: &Path) -> Result<()> {
+        (**self).hard_link(original, destination)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> { (**self).write(path, contents) }
+
+    fn exists(&self, path: &Path) -> bool { (**self).exists(path) }
+
+    fn is_dir(&self, path: &Path) -> bool { (**self).is_dir(path) }
+
+    fn is_file(&self, path: &Path) -> bool { (**self).is_file(path) }
+
+    fn is_symlink(&self, path: &Path) -> bool { (**self).is_symlink(path) }
+}
+
+/// The filesystem on the disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl RealFs {
+    fn convert_metadata(metadata: fs::Metadata) -> Metadata {
+        let file_type = if metadata.is_symlink() {
+            FileType::Symlink
+        } else if metadata.is_dir() {
+            FileType::Dir
+        } else {
+            FileType::File
+        };
+
+        Metadata {
+            file_type,
+            uid: metadata.uid(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+}
+
+impl Filesystem for RealFs {
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        fs::symlink_metadata(path).map(Self::convert_metadata)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        fs::metadata(path).map(Self::convert_metadata)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> { fs::read_link(path) }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> { fs::read(path) }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(path)?
+            .filter_map(|e| e.ok())
+            .map(|element| element.path())
+            .collect())
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> { fs::canonicalize(path) }
+
+    fn create_symlink(&self, original: &Path, destination: &Path) -> Result<()> {
+        unix::fs::symlink(original, destination)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> { fs::create_dir_all(path) }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> { fs::remove_dir(path) }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> { fs::remove_dir_all(path) }
+
+    fn remove_file(&self, path: &Path) -> Result<()> { fs::remove_file(path) }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> { fs::rename(from, to) }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> { fs::copy(from, to).map(|_| ()) }
+
+    fn hard_link(&self, original: &Path, destination: &Path) -> Result<()> {
+        fs::hard_link(original, destination)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> { fs::write(path, contents) }
+}
+
+#[derive(Debug, Clone)]
+enum MemoryNode {
+    Dir,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+struct MemoryEntry {
+    node: MemoryNode,
+    uid:  u32,
+    ino:  u64,
+}
+
+/// A filesystem that only lives in memory. Only absolute paths are supported.
+///
+/// Useful to run [`Stower`](crate::stower::Stower) without touching the disk.
+/// Everything is on the same device. Hard links share the inode number but
+/// not the contents, so a write to one of them is not seen by the others.
+#[derive(Debug)]
+pub struct MemoryFs {
+    entries:  RefCell<BTreeMap<PathBuf, MemoryEntry>>,
+    next_ino: Cell<u64>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(PathBuf::from("/"), MemoryEntry {
+            node: MemoryNode::Dir,
+            uid:  0,
+            ino:  1,
+        });
+
+        Self {
+            entries:  RefCell::new(entries),
+            next_ino: Cell::new(2),
+        }
+    }
+
+    /// Adds a directory and all of its missing parents.
+    pub fn add_dir(&self, path: impl AsRef<Path>) -> Result<()> { self.create_dir(path.as_ref()) }
+
+    /// Adds a file and all of its missing parents.
+    pub fn add_file(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> Result<()> {
+        let path = path.as_ref();
+        self.create_parent(path)?;
+        self.insert(path, MemoryNode::File(contents.into()), 0)
+    }
+
+    /// Adds a symlink and all of its missing parents.
+    pub fn add_symlink(&self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        self.create_parent(path)?;
+        self.create_symlink(target.as_ref(), path)
+    }
+
+    /// Changes the owner of the entry.
+    pub fn set_uid(&self, path: impl AsRef<Path>, uid: u32) -> Result<()> {
+        let path = self.resolve(path.as_ref(), false)?;
+        let mut entries = self.entries.borrow_mut();
+        let entry = entries
+            .get_mut(&path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        entry.uid = uid;
+
+        Ok(())
+    }
+
+    /// Contents of the file at `path`.
+    pub fn contents(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let path = self.resolve(path.as_ref(), true)?;
+        match self.entries.borrow().get(&path).map(|entry| &entry.node) {
+            Some(MemoryNode::File(contents)) => Ok(contents.clone()),
+            Some(_) => Err(io::ErrorKind::InvalidInput.into()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    /// Resolves the symlinks in `path`. The last component is only resolved if
+    /// `follow` is true. The returned path does not need to exist.
+    fn resolve(&self, path: &Path, follow: bool) -> Result<PathBuf> {
+        if path.is_relative() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let mut depth = 0;
+        let mut resolved = PathBuf::from("/");
+        let mut remaining = path
+            .components()
+            .map(|component| component.as_os_str().to_os_string())
+            .collect::<Vec<_>>();
+        remaining.reverse();
+
+        while let Some(component) = remaining.pop() {
+            match Path::new(&component).components().next() {
+                Some(Component::RootDir | Component::CurDir) | None => continue,
+                Some(Component::ParentDir) => {
+                    resolved.pop();
+                    continue;
+                },
+                _ => resolved.push(&component),
+            }
+
+            if remaining.is_empty() && !follow {
+                break;
+            }
+
+            let target = match self.entries.borrow().get(&resolved) {
+                Some(MemoryEntry {
+                    node: MemoryNode::Symlink(target),
+                    ..
+                }) => target.clone(),
+                _ => continue,
+            };
+
+            depth += 1;
+            if depth > MAX_SYMLINK_DEPTH {
+                return Err(io::Error::other(
+                    "Too many levels of symbolic links",
+                ));
+            }
+
+            resolved.pop();
+            if target.is_absolute() {
+                resolved = PathBuf::from("/");
+            }
+            remaining.extend(
+                target
+                    .components()
+                    .rev()
+                    .map(|component| component.as_os_str().to_os_string()),
+            );
+        }
+
+        Ok(resolved)
+    }
+
+    fn get(&self, path: &Path, follow: bool) -> Result<MemoryEntry> {
+        let path = self.resolve(path, follow)?;
+        self.entries
+            .borrow()
+            .get(&path)
+            .cloned()
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    fn create_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(parent) => self.create_dir(parent),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a new entry with a new inode number to an existing directory.
+    fn insert(&self, path: &Path, node: MemoryNode, uid: u32) -> Result<()> {
+        let ino = self.next_ino.get();
+        self.next_ino.set(ino + 1);
+
+        self.insert_entry(path, MemoryEntry { node, uid, ino })
+    }
+
+    /// Adds `entry` to an existing directory.
+    fn insert_entry(&self, path: &Path, entry: MemoryEntry) -> Result<()> {
+        let path = self.resolve(path, false)?;
+        let Some(parent) = path.parent() else {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        };
+
+        if !self.is_dir(parent) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        let mut entries = self.entries.borrow_mut();
+        if entries.contains_key(&path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        entries.insert(path, entry);
+
+        Ok(())
+    }
+
+    fn to_metadata(entry: &MemoryEntry) -> Metadata {
+        Metadata {
+            file_type: match entry.node {
+                MemoryNode::Dir => FileType::Dir,
+                MemoryNode::File(_) => FileType::File,
+                MemoryNode::Symlink(_) => FileType::Symlink,
+            },
+            uid:       entry.uid,
+            dev:       0,
+            ino:       entry.ino,
+        }
+    }
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self { Self::new() }
+}
+
+impl Filesystem for MemoryFs {
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.get(path, false).map(|entry| Self::to_metadata(&entry))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.get(path, true).map(|entry| Self::to_metadata(&entry))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        match self.get(path, false)?.node {
+            MemoryNode::Symlink(target) => Ok(target),
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> { self.contents(path) }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let resolved = self.resolve(path, true)?;
+        if !matches!(self.get(&resolved, false)?.node, MemoryNode::Dir) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+
+        Ok(self
+            .entries
+            .borrow()
+            .keys()
+            .filter(|entry| entry.parent() == Some(&resolved))
+            .filter_map(|entry| entry.file_name())
+            .map(|name| path.join(name))
+            .collect())
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        let resolved = self.resolve(path, true)?;
+        self.get(&resolved, false)?;
+
+        Ok(resolved)
+    }
+
+    fn create_symlink(&self, original: &Path, destination: &Path) -> Result<()> {
+        self.insert(
+            destination,
+            MemoryNode::Symlink(original.to_path_buf()),
+            0,
+        )
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let path = self.resolve(path, true)?;
+        if self.is_dir(&path) {
+            return Ok(());
+        }
+
+        self.create_parent(&path)?;
+        self.insert(&path, MemoryNode::Dir, 0)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        if !self.read_dir(path)?.is_empty() {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+
+        self.remove_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        let path = self.resolve(path, false)?;
+        if !matches!(self.get(&path, false)?.node, MemoryNode::Dir) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+
+        self.entries
+            .borrow_mut()
+            .retain(|entry, _| !entry.starts_with(&path));
+
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let path = self.resolve(path, false)?;
+        if matches!(self.get(&path, false)?.node, MemoryNode::Dir) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        self.entries.borrow_mut().remove(&path);
+
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = self.resolve(from, false)?;
+        let to = self.resolve(to, false)?;
+        let entry = self.get(&from, false)?;
+
+        if matches!(entry.node, MemoryNode::Dir) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        if self.is_dir(&to) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        self.entries.borrow_mut().remove(&to);
+        self.insert_entry(&to, entry)?;
+        self.entries.borrow_mut().remove(&from);
+
+        Ok(())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let contents = self.contents(from)?;
+        let to = self.resolve(to, true)?;
+
+        if self.is_dir(&to) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        self.entries.borrow_mut().remove(&to);
+        self.insert(&to, MemoryNode::File(contents), 0)
+    }
+
+    fn hard_link(&self, original: &Path, destination: &Path) -> Result<()> {
+        let entry = self.get(original, true)?;
+        if !matches!(entry.node, MemoryNode::File(_)) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        self.insert_entry(destination, entry)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let path = self.resolve(path, true)?;
+
+        if self.is_dir(&path) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        self.entries.borrow_mut().remove(&path);
+        self.insert(&path, MemoryNode::File(contents.to_vec()), 0)
+    }
+}
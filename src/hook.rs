@@ -0,0 +1,123 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    error::{Error, Result},
+    filesystem::Filesystem,
+    manifest::PACKAGE_DIR,
+};
+
+/// Directory in [`PACKAGE_DIR`] that holds the hook scripts.
+pub const HOOKS_DIR: &str = "hooks";
+
+/// What is done to a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Stow,
+    Unstow,
+    Restow,
+    Adopt,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stow => "stow",
+            Self::Unstow => "unstow",
+            Self::Restow => "restow",
+            Self::Adopt => "adopt",
+        })
+    }
+}
+
+/// Whether a hook runs before or after the changes are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    Pre,
+    Post,
+}
+
+impl fmt::Display for When {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pre => "pre",
+            Self::Post => "post",
+        })
+    }
+}
+
+/// A script in `<package>/.rustow/hooks` named after the operation it runs
+/// around, e.g. `post-stow`.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub path:      PathBuf,
+    pub package:   PathBuf,
+    pub target:    PathBuf,
+    pub operation: Operation,
+    pub when:      When,
+}
+
+impl Hook {
+    /// The hook of `package` for `operation`, if there is one.
+    pub fn find<F: Filesystem>(
+        fs: &F,
+        package: &Path,
+        target: &Path,
+        operation: Operation,
+        when: When,
+    ) -> Option<Self> {
+        let path = package
+            .join(PACKAGE_DIR)
+            .join(HOOKS_DIR)
+            .join(format!("{when}-{operation}"));
+
+        fs.is_file(&path).then(|| {
+            Self {
+                path,
+                package: package.to_path_buf(),
+                target: target.to_path_buf(),
+                operation,
+                when,
+            }
+        })
+    }
+
+    /// Runs the script in the package directory and waits for it. A non-zero
+    /// exit status is an error.
+    pub fn run(&self, stow_dir: &Path) -> Result<()> {
+        let name = self.package.file_name().unwrap_or_default();
+
+        let status = Command::new(&self.path)
+            .current_dir(&self.package)
+            .env("RUSTOW_PACKAGE", name)
+            .env("RUSTOW_PACKAGE_DIR", &self.package)
+            .env("RUSTOW_STOW_DIR", stow_dir)
+            .env("RUSTOW_TARGET_DIR", &self.target)
+            .env("RUSTOW_OPERATION", self.operation.to_string())
+            .env(
+                "RUSTOW_HOOK",
+                format!("{}-{}", self.when, self.operation),
+            )
+            .status()
+            .map_err(|why| Error::io(&self.path, &self.target, why))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::HookFailed {
+                source: self.path.clone(),
+                target: self.target.clone(),
+                reason: status.to_string(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Running hook: {}", self.path.display())
+    }
+}
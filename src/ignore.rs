@@ -0,0 +1,128 @@
+use std::path::{Component, Path};
+
+use regex::Regex;
+
+/// Name of the file that lists ignore patterns, one per line. It can be in a
+/// package or in the stow directory.
+pub const IGNORE_FILE: &str = ".rustow-ignore";
+
+/// Used when neither the package nor the stow directory has an ignore file.
+/// Same as GNU Stow's default list.
+pub const DEFAULT_IGNORE: &[&str] = &[
+    "RCS",
+    ".+,v",
+    "CVS",
+    r"\.#.+",
+    r"\.cvsignore",
+    r"\.svn",
+    "_darcs",
+    r"\.hg",
+    r"\.git",
+    r"\.gitignore",
+    r"\.gitmodules",
+    ".+~",
+    "#.*#",
+    "^/README.*",
+    "^/LICENSE.*",
+    "^/COPYING",
+];
+
+/// Directories in the stow directory that are never packages, even with
+/// hidden packages included.
+pub const IGNORED_PACKAGES: &[&str] = &[".git", ".hg", ".svn", ".bzr", "_darcs", "CVS", "RCS"];
+
+/// Regexes of paths that are never stowed, with GNU Stow's rules: a pattern
+/// without `/` has to match the whole file name, the others have to match the
+/// whole path from the package root, starting with `/`.
+pub struct IgnoreList {
+    names: Vec<Regex>,
+    paths: Vec<Regex>,
+}
+
+impl IgnoreList {
+    /// Compiles the patterns. On failure, returns the invalid pattern with
+    /// the error.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, (String, regex::Error)> {
+        let mut names = Vec::new();
+        let mut paths = Vec::new();
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let re = Regex::new(&format!("^(?:{pattern})$"))
+                .map_err(|why| (pattern.to_string(), why))?;
+
+            if pattern.contains('/') {
+                paths.push(re);
+            } else {
+                names.push(re);
+            }
+        }
+
+        Ok(Self { names, paths })
+    }
+
+    /// Patterns in the contents of an ignore file. Empty lines and lines
+    /// starting with `#` are skipped.
+    pub fn parse(contents: &str) -> Vec<String> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Whether `path`, relative to the package root, matches one of the
+    /// patterns.
+    pub fn matches(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        let name = name.to_string_lossy();
+
+        let full_path = path
+            .components()
+            .filter_map(|component| {
+                match component {
+                    Component::Normal(part) => Some(format!("/{}", part.to_string_lossy())),
+                    _ => None,
+                }
+            })
+            .collect::<String>();
+
+        self.names.iter().any(|re| re.is_match(&name))
+            || self.paths.iter().any(|re| re.is_match(&full_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_names_and_paths() {
+        let list = IgnoreList::new(DEFAULT_IGNORE).unwrap();
+
+        for (path, expected) in [
+            (".git", true),
+            ("nvim/.git", true),
+            (".github", false),
+            ("file~", true),
+            ("#notes#", true),
+            ("README.md", true),
+            ("nvim/README.md", false),
+            ("LICENSE-MIT", true),
+            (".bashrc", false),
+        ] {
+            assert_eq!(list.matches(Path::new(path)), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn parse_skips_empty_lines_and_comments() {
+        assert_eq!(
+            IgnoreList::parse("# comment\n\n  \\.cache  \n^/local/.*\n"),
+            [r"\.cache", "^/local/.*"]
+        );
+    }
+}
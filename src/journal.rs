@@ -0,0 +1,267 @@
+use std::{
+    env,
+    io::{self, Result},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::filesystem::Filesystem;
+
+/// An operation that has been applied to the filesystem, with everything
+/// needed to undo it.
+#[derive(Debug)]
+enum Entry {
+    CreatedSymlink(PathBuf),
+    RemovedSymlink { path: PathBuf, target: PathBuf },
+    CreatedDir(PathBuf),
+    CreatedFile(PathBuf),
+    RemovedDir(PathBuf),
+    Moved { from: PathBuf, to: PathBuf },
+}
+
+/// Applies filesystem operations while recording them, so all of them can be
+/// undone if something goes wrong in the middle of a run.
+///
+/// Files are never deleted until the journal is committed. Instead, they are
+/// moved to a backup directory and brought back on rollback.
+pub struct Journal<'a, F: Filesystem> {
+    fs:         &'a F,
+    entries:    Vec<Entry>,
+    backup_dir: PathBuf,
+    backups:    usize,
+}
+
+impl<'a, F: Filesystem> Journal<'a, F> {
+    pub fn new(fs: &'a F) -> Self {
+        Self {
+            fs,
+            entries: Vec::new(),
+            backup_dir: env::temp_dir().join(format!("rustow-backup-{}", process::id())),
+            backups: 0,
+        }
+    }
+
+    pub fn create_symlink(&mut self, original: &Path, destination: &Path) -> Result<()> {
+        self.fs.create_symlink(original, destination)?;
+        self.entries
+            .push(Entry::CreatedSymlink(destination.to_path_buf()));
+
+        Ok(())
+    }
+
+    pub fn remove_symlink(&mut self, target: &Path) -> Result<()> {
+        let link_target = self.fs.read_link(target)?;
+        self.fs.remove_file(target)?;
+        self.entries.push(Entry::RemovedSymlink {
+            path:   target.to_path_buf(),
+            target: link_target,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_dir(&mut self, target: &Path) -> Result<()> {
+        // Record every missing parent, so rollback removes all of them
+        let mut created = target
+            .ancestors()
+            .take_while(|ancestor| !self.fs.exists(ancestor))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        self.fs.create_dir(target)?;
+
+        created.reverse();
+        self.entries
+            .extend(created.into_iter().map(Entry::CreatedDir));
+
+        Ok(())
+    }
+
+    /// Removes the empty directory `target`. Never removes anything inside it.
+    pub fn remove_dir(&mut self, target: &Path) -> Result<()> {
+        self.fs.remove_dir(target)?;
+        self.entries.push(Entry::RemovedDir(target.to_path_buf()));
+
+        Ok(())
+    }
+
+    pub fn remove_file(&mut self, target: &Path) -> Result<()> { self.backup(target) }
+
+    pub fn hard_link(&mut self, original: &Path, target: &Path) -> Result<()> {
+        self.fs.hard_link(original, target)?;
+        self.entries.push(Entry::CreatedFile(target.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Copies `original` to `target`, which must not exist.
+    pub fn copy_file(&mut self, original: &Path, target: &Path) -> Result<()> {
+        self.fs.copy(original, target)?;
+        self.entries.push(Entry::CreatedFile(target.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Creates the file `target`, which must not exist.
+    pub fn write_file(&mut self, target: &Path, contents: &[u8]) -> Result<()> {
+        self.fs.write(target, contents)?;
+        self.entries.push(Entry::CreatedFile(target.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Moves `from` to `to`. If `to` already exists, it is replaced.
+    pub fn move_file(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.fs.exists(to) {
+            self.backup(to)?;
+        }
+
+        self.move_entry(from, to)
+    }
+
+    /// Undoes every applied operation in reverse order. Returns the errors of
+    /// the operations that could not be undone.
+    pub fn rollback(mut self) -> Vec<(PathBuf, io::Error)> {
+        let mut errors = Vec::new();
+
+        while let Some(entry) = self.entries.pop() {
+            let (path, result) = match entry {
+                Entry::CreatedSymlink(path) | Entry::CreatedFile(path) => {
+                    let result = self.fs.remove_file(&path);
+                    (path, result)
+                },
+                Entry::RemovedSymlink { path, target } => {
+                    let result = self.fs.create_symlink(&target, &path);
+                    (path, result)
+                },
+                Entry::CreatedDir(path) => {
+                    let result = self.fs.remove_dir(&path);
+                    (path, result)
+                },
+                Entry::RemovedDir(path) => {
+                    let result = self.fs.create_dir(&path);
+                    (path, result)
+                },
+                Entry::Moved { from, to } => {
+                    let result = Self::move_path(self.fs, &to, &from);
+                    (from, result)
+                },
+            };
+
+            if let Err(why) = result {
+                errors.push((path, why));
+            }
+        }
+
+        self.fs.remove_dir(&self.backup_dir).ok();
+
+        errors
+    }
+
+    /// Makes the applied operations permanent by deleting the backups.
+    pub fn commit(self) {
+        if self.backups > 0 {
+            self.fs.remove_dir_all(&self.backup_dir).ok();
+        }
+    }
+
+    fn backup(&mut self, target: &Path) -> Result<()> {
+        self.fs.create_dir(&self.backup_dir)?;
+
+        let backup_path = self.backup_dir.join(self.backups.to_string());
+        self.backups += 1;
+
+        self.move_entry(target, &backup_path)
+    }
+
+    fn move_entry(&mut self, from: &Path, to: &Path) -> Result<()> {
+        Self::move_path(self.fs, from, to)?;
+        self.entries.push(Entry::Moved {
+            from: from.to_path_buf(),
+            to:   to.to_path_buf(),
+        });
+
+        Ok(())
+    }
+
+    fn move_path(fs: &F, from: &Path, to: &Path) -> Result<()> {
+        if fs.rename(from, to).is_err() {
+            fs.copy(from, to)?;
+            fs.remove_file(from)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFs;
+
+    #[test]
+    fn rollback_undoes_the_applied_operations() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "").unwrap();
+        fs.add_file("/etc/profile", "").unwrap();
+        fs.add_symlink("/home/.profile", "/etc/profile").unwrap();
+        fs.add_file("/home/.vimrc", "mine").unwrap();
+        fs.add_file("/home/.gitconfig", "mine").unwrap();
+        fs.add_file("/home/dots/a/.gitconfig", "package").unwrap();
+
+        let mut journal = Journal::new(&fs);
+        journal.create_dir(Path::new("/home/.config/app")).unwrap();
+        journal
+            .create_symlink(
+                Path::new("dots/a/.bashrc"),
+                Path::new("/home/.bashrc"),
+            )
+            .unwrap();
+        journal.remove_symlink(Path::new("/home/.profile")).unwrap();
+        journal.remove_file(Path::new("/home/.vimrc")).unwrap();
+        journal
+            .write_file(Path::new("/home/.config/app/config"), b"")
+            .unwrap();
+        journal
+            .move_file(
+                Path::new("/home/.gitconfig"),
+                Path::new("/home/dots/a/.gitconfig"),
+            )
+            .unwrap();
+        // The run stops at the first failing operation
+        assert!(journal
+            .create_symlink(
+                Path::new("dots/a/.bashrc"),
+                Path::new("/home/.bashrc"),
+            )
+            .is_err());
+
+        assert!(journal.rollback().is_empty());
+        assert!(!fs.exists(Path::new("/home/.config")));
+        assert!(!fs.exists(Path::new("/home/.bashrc")));
+        assert_eq!(
+            fs.read_link(Path::new("/home/.profile")).unwrap(),
+            Path::new("/etc/profile")
+        );
+        assert_eq!(fs.contents("/home/.vimrc").unwrap(), b"mine");
+        assert_eq!(fs.contents("/home/.gitconfig").unwrap(), b"mine");
+        assert_eq!(
+            fs.contents("/home/dots/a/.gitconfig").unwrap(),
+            b"package"
+        );
+    }
+
+    #[test]
+    fn commit_keeps_the_applied_operations() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/.vimrc", "mine").unwrap();
+
+        let mut journal = Journal::new(&fs);
+        let backup_dir = journal.backup_dir.clone();
+        journal.remove_file(Path::new("/home/.vimrc")).unwrap();
+        assert!(fs.exists(&backup_dir));
+
+        journal.commit();
+        assert!(!fs.exists(Path::new("/home/.vimrc")));
+        assert!(!fs.exists(&backup_dir));
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// A special directory name defined by the user. Everything inside a
+/// directory named `@<keyword>` is stowed to the path of the keyword instead
+/// of the target directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "KeywordConfig")]
+pub struct Keyword {
+    /// Candidate paths in order. `$VAR`, `${VAR}` and a leading `~` are
+    /// expanded.
+    paths:          Vec<String>,
+    security_check: bool,
+    /// Whether candidates that expand to a relative path are skipped, as the
+    /// XDG Base Directory Specification requires.
+    absolute:       bool,
+}
+
+impl Keyword {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            paths:          vec![path.into()],
+            security_check: false,
+            absolute:       false,
+        }
+    }
+
+    /// Keyword for the XDG variable `name`.
+    fn xdg(name: &str) -> Self {
+        Self {
+            absolute: true,
+            ..Self::new(format!("${name}"))
+        }
+    }
+
+    /// Path to use if the ones before it use an unset variable.
+    pub fn fallback(mut self, path: impl Into<String>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Requires everything inside the keyword directory to be owned by root,
+    /// like `@root`.
+    pub fn security_check(mut self, security_check: bool) -> Self {
+        self.security_check = security_check;
+        self
+    }
+
+    pub fn needs_security_check(&self) -> bool { self.security_check }
+
+    /// The first candidate path whose variables are all set. On failure,
+    /// returns the first variable that is not set.
+    pub fn resolve(&self) -> Result<PathBuf, String> {
+        let mut missing = None;
+
+        for path in &self.paths {
+            match expand(path) {
+                // Relative XDG directories are invalid, so they count as unset
+                Ok(expanded) if self.absolute && Path::new(&expanded).is_relative() => {
+                    missing.get_or_insert(path.trim_start_matches('$').to_string());
+                },
+                Ok(path) => return Ok(PathBuf::from(path)),
+                Err(name) => {
+                    missing.get_or_insert(name);
+                },
+            }
+        }
+
+        Err(missing.unwrap_or_default())
+    }
+}
+
+/// Built-in keyword with the given name, without `@`. XDG directories fall
+/// back to the defaults in the XDG Base Directory Specification when their
+/// variable is unset or relative.
+pub fn builtin(name: &str) -> Option<Keyword> {
+    let keyword = match name {
+        "xdg-config" => Keyword::xdg("XDG_CONFIG_HOME").fallback("~/.config"),
+        "xdg-data" => Keyword::xdg("XDG_DATA_HOME").fallback("~/.local/share"),
+        "xdg-state" => Keyword::xdg("XDG_STATE_HOME").fallback("~/.local/state"),
+        "xdg-cache" => Keyword::xdg("XDG_CACHE_HOME").fallback("~/.cache"),
+        // Not in the specification as a variable, but used by some tools
+        "xdg-bin" => Keyword::xdg("XDG_BIN_HOME").fallback("~/.local/bin"),
+        // The specification has no default for it
+        "xdg-runtime" => Keyword::xdg("XDG_RUNTIME_DIR"),
+        _ => return None,
+    };
+
+    Some(keyword)
+}
+
+/// Whether `name`, without `@`, is a built-in keyword. Keywords with these
+/// names cannot be defined.
+pub fn is_builtin(name: &str) -> bool {
+    matches!(name, "home" | "root") || name.starts_with("env:") || builtin(name).is_some()
+}
+
+/// How a keyword is written in the config file: either only the path or a
+/// table with the other options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeywordConfig {
+    Path(String),
+    Table(KeywordTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeywordTable {
+    path:           String,
+    #[serde(default)]
+    fallback:       Vec<String>,
+    #[serde(default)]
+    security_check: bool,
+}
+
+impl From<KeywordConfig> for Keyword {
+    fn from(config: KeywordConfig) -> Self {
+        match config {
+            KeywordConfig::Path(path) => Self::new(path),
+            KeywordConfig::Table(table) => {
+                let mut paths = vec![table.path];
+                paths.extend(table.fallback);
+
+                Self {
+                    paths,
+                    security_check: table.security_check,
+                    absolute: false,
+                }
+            },
+        }
+    }
+}
+
+/// Expands `$VAR`, `${VAR}` and a leading `~` in `value`. Empty variables
+/// count as unset. On failure, returns the name of the unset variable.
+pub fn expand(value: &str) -> Result<String, String> {
+    let var = |name: &str| {
+        env::var(name)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| name.to_string())
+    };
+
+    let mut result = String::new();
+    let mut rest = value;
+
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with('/') {
+            result.push_str(&var("HOME")?);
+            rest = after;
+        }
+    }
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[.. index]);
+        rest = &rest[index + 1 ..];
+
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[.. end], &braced[end + 1 ..]),
+                None => ("", rest),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[.. end], &rest[end ..])
+        };
+
+        if name.is_empty() {
+            result.push('$');
+        } else {
+            result.push_str(&var(name)?);
+            rest = after;
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_replaces_variables() {
+        env::set_var("RUSTOW_TEST_VALUE", "value");
+        env::set_var("RUSTOW_TEST_EMPTY", "");
+        env::remove_var("RUSTOW_TEST_UNSET");
+        let home = env::var("HOME").unwrap();
+
+        for (value, expected) in [
+            ("plain", Ok("plain".to_string())),
+            ("$RUSTOW_TEST_VALUE", Ok("value".to_string())),
+            (
+                "${RUSTOW_TEST_VALUE}/dir",
+                Ok("value/dir".to_string()),
+            ),
+            (
+                "a-$RUSTOW_TEST_VALUE-b",
+                Ok("a-value-b".to_string()),
+            ),
+            ("$ and ${", Ok("$ and ${".to_string())),
+            ("~", Ok(home.clone())),
+            ("~/dir", Ok(format!("{home}/dir"))),
+            ("~user", Ok("~user".to_string())),
+            ("dir/~", Ok("dir/~".to_string())),
+            (
+                "$RUSTOW_TEST_UNSET",
+                Err("RUSTOW_TEST_UNSET".to_string()),
+            ),
+            (
+                "${RUSTOW_TEST_EMPTY}",
+                Err("RUSTOW_TEST_EMPTY".to_string()),
+            ),
+        ] {
+            assert_eq!(expand(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn resolve_ignores_relative_xdg_directories() {
+        let config = builtin("xdg-config").unwrap();
+        let runtime = builtin("xdg-runtime").unwrap();
+        let default = PathBuf::from(expand("~/.config").unwrap());
+
+        env::set_var("XDG_CONFIG_HOME", "/xdg/config");
+        env::set_var("XDG_RUNTIME_DIR", "/run/user/1000");
+        assert_eq!(config.resolve(), Ok(PathBuf::from("/xdg/config")));
+        assert_eq!(
+            runtime.resolve(),
+            Ok(PathBuf::from("/run/user/1000"))
+        );
+
+        env::set_var("XDG_CONFIG_HOME", "config");
+        env::set_var("XDG_RUNTIME_DIR", "run");
+        assert_eq!(config.resolve(), Ok(default));
+        assert_eq!(
+            runtime.resolve(),
+            Err("XDG_RUNTIME_DIR".to_string())
+        );
+
+        // Only built-in keywords follow the specification
+        assert_eq!(
+            Keyword::new("$XDG_CONFIG_HOME").resolve(),
+            Ok(PathBuf::from("config"))
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! GNU Stow like symlink farm manager.
+//!
+//! Everything is done through a [`Stower`], which is created with a
+//! [`StowerBuilder`]. The command line interface is a thin layer on top of it.
+
+mod config;
+mod error;
+mod filesystem;
+mod hook;
+mod ignore;
+mod journal;
+mod keyword;
+mod manifest;
+mod overlay;
+mod plan;
+mod report;
+mod stower;
+mod template;
+
+pub use crate::{
+    config::{Config, CONFIG_FILE, STOWRC_FILE},
+    error::{Error, Result},
+    filesystem::{FileType, Filesystem, MemoryFs, Metadata, RealFs, MAX_SYMLINK_DEPTH},
+    hook::{Hook, Operation, When},
+    keyword::Keyword,
+    manifest::{Manifest, MANIFEST_FILE, PACKAGE_DIR},
+    plan::{Action, Skip},
+    report::{FileReport, FileState, PackageReport, PackageState, RunReport},
+    stower::{ConflictPolicy, LinkMode, Status, Stower, StowerBuilder},
+};
@@ -1,10 +1,26 @@
 mod cmd;
-mod stower;
+
+use std::process;
 
 use clap::Parser;
+use rustow::{Config, RealFs, Result, Status, Stower};
+
+use crate::cmd::Args;
+
+fn main() {
+    let status = match build(Args::parse()) {
+        Ok(stower) => stower.run().status,
+        Err(why) => {
+            println!("{why}");
+            Status::from_error(&why)
+        },
+    };
 
-use crate::{cmd::Args, stower::Stower};
+    process::exit(status.code());
+}
 
-// TODO: add tests
+fn build(args: Args) -> Result<Stower> {
+    let config = Config::discover(&RealFs, args.stow_dir.as_deref())?;
 
-fn main() { Stower::new(Args::parse()).unwrap().run(); }
+    args.into_builder(config).build()
+}
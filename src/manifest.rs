@@ -0,0 +1,90 @@
+use std::{
+    env,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    filesystem::Filesystem,
+    stower::LinkMode,
+};
+
+/// Directory in a package that holds its metadata. It is never stowed.
+pub const PACKAGE_DIR: &str = ".rustow";
+
+/// Name of the manifest file in [`PACKAGE_DIR`].
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+/// Optional settings of a single package, read from
+/// `<package>/.rustow/manifest.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub description:  Option<String>,
+    /// Target directory of the package. Relative paths are relative to the
+    /// target directory of the run.
+    pub target_dir:   Option<PathBuf>,
+    /// Special keywords the package may use. Every keyword is allowed if not
+    /// set.
+    pub keywords:     Option<Vec<String>>,
+    /// Environment variables that must be set to stow the package.
+    pub required_env: Vec<String>,
+    pub tags:         Vec<String>,
+    /// How the files of the package are placed in the target.
+    pub mode:         Option<LinkMode>,
+    /// Patterns of files that are always copied, with the same rules as
+    /// ignore patterns.
+    pub copy:         Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest of `package`. A package without one has an empty
+    /// manifest.
+    pub fn load<F: Filesystem>(fs: &F, package: &Path) -> Result<Self> {
+        let path = package.join(PACKAGE_DIR).join(MANIFEST_FILE);
+        let contents = match fs.read(&path) {
+            Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(why) => return Err(Error::io(&path, &path, why)),
+        };
+
+        toml::from_str(&contents).map_err(|why| {
+            Error::InvalidConfig {
+                source: path.clone(),
+                target: path.clone(),
+                error:  why.to_string(),
+            }
+        })
+    }
+
+    /// Whether the special path named `name` may be used. `@env` allows every
+    /// `@env:<name>`.
+    pub fn allows_keyword(&self, name: &str) -> bool {
+        let Some(keywords) = &self.keywords else {
+            return true;
+        };
+        let name = name.strip_prefix('@').unwrap_or(name);
+
+        keywords.iter().any(|keyword| {
+            let keyword = keyword.strip_prefix('@').unwrap_or(keyword);
+
+            keyword == name || (keyword == "env" && name.starts_with("env:"))
+        })
+    }
+
+    /// First required environment variable that is not set.
+    pub fn missing_env(&self) -> Option<&str> {
+        self.required_env
+            .iter()
+            .find(|name| env::var_os(name).is_none_or(|value| value.is_empty()))
+            .map(String::as_str)
+    }
+
+    /// Whether the package has one of `tags`. Every package matches no tags.
+    pub fn has_tag(&self, tags: &[String]) -> bool {
+        tags.is_empty() || self.tags.iter().any(|tag| tags.contains(tag))
+    }
+}
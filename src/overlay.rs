@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    filesystem::{Filesystem, MAX_SYMLINK_DEPTH},
+    plan::Action,
+};
+
+/// State of a path after all the pending actions are applied.
+#[derive(Debug, Clone)]
+enum Node {
+    Missing,
+    Dir,
+    /// Contents are only known for files written by an action.
+    File(Option<Vec<u8>>),
+    /// A file that is a hard link of the given path.
+    HardLink(PathBuf),
+    Symlink(PathBuf),
+}
+
+/// In-memory layer of pending changes on top of another filesystem.
+///
+/// Every planned action is applied to the overlay instead of the disk, so
+/// later checks in the same run (e.g. stowing after unstowing in restow, or
+/// simulating the whole run) see the filesystem as it will be instead of as it
+/// is.
+pub struct Overlay<'a, F: Filesystem> {
+    base:    &'a F,
+    changes: HashMap<PathBuf, Node>,
+}
+
+impl<'a, F: Filesystem> Overlay<'a, F> {
+    pub fn new(base: &'a F) -> Self { Self { base, changes: HashMap::new() } }
+
+    pub fn apply(&mut self, action: &Action) {
+        match action {
+            Action::CreateSymlink { original, destination } =>
+                self.set(destination, Node::Symlink(original.clone())),
+            Action::RemoveSymlink(target)
+            | Action::RemoveDir(target)
+            | Action::RemoveFile(target) => self.set(target, Node::Missing),
+            Action::CreateHardLink { original, destination } =>
+                self.set(destination, Node::HardLink(original.clone())),
+            Action::CreateDir(target) => self.set(target, Node::Dir),
+            Action::MoveFile { from, to } => {
+                self.set(from, Node::Missing);
+                self.set(to, Node::File(None));
+            },
+            Action::WriteFile { destination, contents, .. } =>
+                self.set(destination, Node::File(Some(contents.clone()))),
+            Action::CopyFile { original, destination } => {
+                let contents = self.read(original).ok();
+                self.set(destination, Node::File(contents));
+            },
+        }
+    }
+
+    pub fn exists(&self, path: &Path) -> bool { !matches!(self.follow(path), Node::Missing) }
+
+    pub fn is_dir(&self, path: &Path) -> bool { matches!(self.follow(path), Node::Dir) }
+
+    pub fn is_file(&self, path: &Path) -> bool {
+        matches!(
+            self.follow(path),
+            Node::File(_) | Node::HardLink(_)
+        )
+    }
+
+    /// Whether `path` is a hard link of the file `original` in the base
+    /// filesystem, i.e. both have the same device and inode numbers.
+    pub fn is_hard_link(&self, path: &Path, original: &Path) -> bool {
+        match self.changed_ancestor(path) {
+            Some((ancestor, Node::HardLink(target))) if ancestor == path => target == original,
+            Some(_) => false,
+            None =>
+                match (
+                    self.base.symlink_metadata(path),
+                    self.base.symlink_metadata(original),
+                ) {
+                    (Ok(metadata), Ok(original)) =>
+                        metadata.is_file()
+                            && metadata.dev == original.dev
+                            && metadata.ino == original.ino,
+                    _ => false,
+                },
+        }
+    }
+
+    pub fn is_symlink(&self, path: &Path) -> bool { matches!(self.node(path), Node::Symlink(_)) }
+
+    /// Resolves all the symlinks in `path` (like [`Filesystem::canonicalize`])
+    /// while taking applied actions into account.
+    pub fn canonicalize(&self, path: &Path) -> Option<PathBuf> {
+        let mut path = path.to_path_buf();
+
+        for _ in 0 .. MAX_SYMLINK_DEPTH {
+            match self.node(&path) {
+                Node::Missing => return None,
+                Node::Symlink(target) => path = Self::link_destination(&path, &target),
+                _ =>
+                    return if self.is_changed(&path) {
+                        Some(path)
+                    } else {
+                        self.base.canonicalize(&path).ok()
+                    },
+            }
+        }
+
+        None
+    }
+
+    /// Contents of the file at `path` after applied actions. Fails if they are
+    /// not known before the actions are applied.
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.changed_ancestor(path) {
+            Some((ancestor, Node::File(Some(contents)))) if ancestor == path =>
+                Ok(contents.clone()),
+            Some((ancestor, Node::HardLink(original))) if ancestor == path => self.read(original),
+            Some(_) =>
+                Err(io::Error::other(
+                    "contents are not known before applying",
+                )),
+            None => self.base.read(path),
+        }
+    }
+
+    /// Lists the entries of `path` after applied actions.
+    pub fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = match self.changed_ancestor(path) {
+            Some((ancestor, Node::Symlink(target))) => {
+                let rest = path.strip_prefix(ancestor).expect("Cannot fail");
+                let real_path = Self::link_destination(ancestor, target).join(rest);
+
+                return Ok(self
+                    .read_dir(&real_path)?
+                    .into_iter()
+                    .filter_map(|entry| entry.file_name().map(|name| path.join(name)))
+                    .collect());
+            },
+            Some((ancestor, Node::Dir)) if ancestor == path => Vec::new(),
+            Some(_) => return Err(io::ErrorKind::NotFound.into()),
+            None => self.base.read_dir(path)?,
+        };
+
+        let new_entries = self
+            .changes
+            .keys()
+            .filter(|changed| changed.parent() == Some(path) && !entries.contains(changed))
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.extend(new_entries);
+        entries.retain(|entry| !matches!(self.node(entry), Node::Missing));
+
+        Ok(entries)
+    }
+
+    fn set(&mut self, path: &Path, node: Node) {
+        // Anything inside the changed path is either gone or reachable through the
+        // new node now
+        self.changes
+            .retain(|changed, _| changed == path || !changed.starts_with(path));
+        self.changes.insert(path.to_path_buf(), node);
+    }
+
+    /// Whether `path` or one of its parents is changed by an applied action.
+    fn is_changed(&self, path: &Path) -> bool { self.changed_ancestor(path).is_some() }
+
+    /// The closest change to `path`, starting from `path` itself.
+    fn changed_ancestor<'b>(&'b self, path: &'b Path) -> Option<(&'b Path, &'b Node)> {
+        path.ancestors()
+            .find_map(|ancestor| self.changes.get(ancestor).map(|node| (ancestor, node)))
+    }
+
+    fn node(&self, path: &Path) -> Node {
+        match self.changed_ancestor(path) {
+            Some((ancestor, node)) if ancestor == path => node.clone(),
+            Some((ancestor, Node::Symlink(target))) => {
+                let rest = path.strip_prefix(ancestor).expect("Cannot fail");
+                self.node(&Self::link_destination(ancestor, target).join(rest))
+            },
+            // Directories created by the overlay are empty and removed paths or files cannot
+            // contain anything
+            Some(_) => Node::Missing,
+            None =>
+                match self.base.symlink_metadata(path) {
+                    Ok(metadata) if metadata.is_symlink() =>
+                        self.base
+                            .read_link(path)
+                            .map_or(Node::Missing, Node::Symlink),
+                    Ok(metadata) if metadata.is_dir() => Node::Dir,
+                    Ok(_) => Node::File(None),
+                    Err(_) => Node::Missing,
+                },
+        }
+    }
+
+    fn follow(&self, path: &Path) -> Node {
+        let mut path = path.to_path_buf();
+
+        for _ in 0 .. MAX_SYMLINK_DEPTH {
+            match self.node(&path) {
+                Node::Symlink(target) => path = Self::link_destination(&path, &target),
+                node => return node,
+            }
+        }
+
+        Node::Missing
+    }
+
+    fn link_destination(link: &Path, target: &Path) -> PathBuf {
+        match link.parent() {
+            Some(parent) if target.is_relative() => parent.join(target),
+            _ => target.to_path_buf(),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Error,
+    filesystem::Filesystem,
+    hook::Hook,
+    overlay::Overlay,
+    report::{FileReport, RunReport},
+    stower::Status,
+};
+
+/// A single change on the filesystem. Planning phase only produces these, they
+/// are applied after the whole plan is known to be conflict-free.
+#[derive(Debug, Clone)]
+pub enum Action {
+    CreateSymlink {
+        original:    PathBuf,
+        destination: PathBuf,
+    },
+    RemoveSymlink(PathBuf),
+    CreateHardLink {
+        original:    PathBuf,
+        destination: PathBuf,
+    },
+    CreateDir(PathBuf),
+    RemoveDir(PathBuf),
+    RemoveFile(PathBuf),
+    MoveFile {
+        from: PathBuf,
+        to:   PathBuf,
+    },
+    CopyFile {
+        original:    PathBuf,
+        destination: PathBuf,
+    },
+    /// Creates a file rendered from the template `original`.
+    WriteFile {
+        original:    PathBuf,
+        destination: PathBuf,
+        contents:    Vec<u8>,
+    },
+}
+
+/// A path that is left as it is.
+#[derive(Debug, Clone)]
+pub struct Skip {
+    pub path:     PathBuf,
+    pub reason:   String,
+    /// Whether the path is in the way (e.g. a file is where a symlink should
+    /// be) instead of already being as it should be.
+    pub conflict: bool,
+}
+
+impl Action {
+    /// The path the action reads from and the path it changes. Both are the
+    /// same for actions that only change a single path.
+    pub fn paths(&self) -> (&Path, &Path) {
+        match self {
+            Self::CreateSymlink { original, destination } => (original, destination),
+            Self::RemoveSymlink(target)
+            | Self::CreateDir(target)
+            | Self::RemoveDir(target)
+            | Self::RemoveFile(target) => (target, target),
+            Self::MoveFile { from, to } => (from, to),
+            Self::CreateHardLink { original, destination }
+            | Self::CopyFile { original, destination }
+            | Self::WriteFile { original, destination, .. } => (original, destination),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateSymlink { original, destination } =>
+                write!(
+                    f,
+                    "Creating symlink: {} -> {}",
+                    destination.display(),
+                    original.display()
+                ),
+            Self::RemoveSymlink(target) => write!(f, "Removing symlink: {}", target.display()),
+            Self::CreateHardLink { original, destination } =>
+                write!(
+                    f,
+                    "Creating hard link: {} -> {}",
+                    destination.display(),
+                    original.display()
+                ),
+            Self::CreateDir(target) => write!(f, "Creating directory: {}", target.display()),
+            Self::RemoveDir(target) => write!(f, "Removing directory: {}", target.display()),
+            Self::RemoveFile(target) => write!(f, "Removing file: {}", target.display()),
+            Self::MoveFile { from, to } =>
+                write!(
+                    f,
+                    "Moving file: {} -> {}",
+                    from.display(),
+                    to.display()
+                ),
+            Self::CopyFile { original, destination } =>
+                write!(
+                    f,
+                    "Copying file: {} -> {}",
+                    destination.display(),
+                    original.display()
+                ),
+            Self::WriteFile { original, destination, .. } =>
+                write!(
+                    f,
+                    "Rendering template: {} -> {}",
+                    destination.display(),
+                    original.display()
+                ),
+        }
+    }
+}
+
+/// Ordered list of actions and conflicts found while walking the packages.
+///
+/// Every pushed action is also applied to an [`Overlay`], which the planner
+/// uses to inspect the filesystem.
+pub struct Plan<'a, F: Filesystem> {
+    actions:   Vec<Action>,
+    conflicts: Vec<Error>,
+    skipped:   Vec<Skip>,
+    errors:    Vec<Error>,
+    hooks:     Vec<Hook>,
+    /// Hooks that ran successfully.
+    hooks_run: Vec<Hook>,
+    /// States of the package files, when only the status is asked.
+    reports:   Vec<FileReport>,
+    /// Directories special paths point to.
+    roots:     HashSet<PathBuf>,
+    overlay:   Overlay<'a, F>,
+}
+
+impl<'a, F: Filesystem> Plan<'a, F> {
+    pub fn new(fs: &'a F) -> Self {
+        Self {
+            actions:   Vec::new(),
+            conflicts: Vec::new(),
+            skipped:   Vec::new(),
+            errors:    Vec::new(),
+            hooks:     Vec::new(),
+            hooks_run: Vec::new(),
+            reports:   Vec::new(),
+            roots:     HashSet::new(),
+            overlay:   Overlay::new(fs),
+        }
+    }
+
+    pub fn actions(&self) -> &[Action] { &self.actions }
+
+    /// Paths that cannot be handled without user intervention.
+    pub fn conflicts(&self) -> &[Error] { &self.conflicts }
+
+    /// Errors that prevented a path from being planned.
+    pub fn errors(&self) -> &[Error] { &self.errors }
+
+    /// Hooks to run around the actions, in order.
+    pub fn hooks(&self) -> &[Hook] { &self.hooks }
+
+    /// Hooks that ran successfully.
+    pub fn hooks_run(&self) -> &[Hook] { &self.hooks_run }
+
+    pub fn reports(&self) -> &[FileReport] { &self.reports }
+
+    /// The filesystem as it will be after the planned actions are applied.
+    pub fn fs(&self) -> &Overlay<'a, F> { &self.overlay }
+
+    pub fn push(&mut self, action: Action) {
+        self.overlay.apply(&action);
+        self.actions.push(action);
+    }
+
+    pub fn conflict(&mut self, conflict: Error) { self.conflicts.push(conflict); }
+
+    /// Records a path that does not need any change.
+    pub fn skip(&mut self, path: &Path, reason: impl Into<String>) {
+        self.skipped.push(Skip {
+            path:     path.to_path_buf(),
+            reason:   reason.into(),
+            conflict: false,
+        });
+    }
+
+    /// Records a path that is left as it is because it is in the way.
+    pub fn skip_conflict(&mut self, path: &Path, reason: impl Into<String>) {
+        self.skipped.push(Skip {
+            path:     path.to_path_buf(),
+            reason:   reason.into(),
+            conflict: true,
+        });
+    }
+
+    /// Whether any path is skipped because of an error or a conflict.
+    pub fn has_problems(&self) -> bool {
+        !self.errors.is_empty() || self.skipped.iter().any(|skip| skip.conflict)
+    }
+
+    pub fn error(&mut self, error: Error) { self.errors.push(error); }
+
+    pub fn hook(&mut self, hook: Hook) { self.hooks.push(hook); }
+
+    pub fn hook_run(&mut self, hook: Hook) { self.hooks_run.push(hook); }
+
+    pub fn report(&mut self, report: FileReport) { self.reports.push(report); }
+
+    /// Records a directory a special path points to. These are never replaced
+    /// or removed.
+    pub fn add_root(&mut self, path: PathBuf) { self.roots.insert(path); }
+
+    pub fn is_root(&self, path: &Path) -> bool { self.roots.contains(path) }
+
+    /// Report of a run that ended with `status`. `actions` and `hooks` are the
+    /// ones that were applied and ran.
+    pub fn into_report(self, status: Status, actions: Vec<Action>, hooks: Vec<Hook>) -> RunReport {
+        RunReport {
+            status,
+            actions,
+            hooks,
+            conflicts: self.conflicts,
+            skipped: self.skipped,
+            errors: self.errors,
+            packages: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::{fmt, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    hook::Hook,
+    plan::{Action, Skip},
+    stower::Status,
+};
+
+/// Whether a single file of a package is in place in the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    Stowed,
+    NotStowed,
+    /// Something else is where the file should be.
+    Conflict,
+}
+
+impl fmt::Display for FileState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stowed => "stowed",
+            Self::NotStowed => "not stowed",
+            Self::Conflict => "conflict",
+        })
+    }
+}
+
+/// State of a package, found from the states of its files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageState {
+    Stowed,
+    Partial,
+    NotStowed,
+    /// At least one file conflicts with something in the target.
+    Conflicting,
+    /// There is no such package in the stow directory.
+    Missing,
+}
+
+impl PackageState {
+    /// A package without any files is not stowed.
+    pub fn of(files: &[FileReport]) -> Self {
+        let stowed = files
+            .iter()
+            .filter(|file| file.state == FileState::Stowed)
+            .count();
+
+        if files.iter().any(|file| file.state == FileState::Conflict) {
+            Self::Conflicting
+        } else if stowed == 0 {
+            Self::NotStowed
+        } else if stowed == files.len() {
+            Self::Stowed
+        } else {
+            Self::Partial
+        }
+    }
+}
+
+impl fmt::Display for PackageState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stowed => "stowed",
+            Self::Partial => "partially stowed",
+            Self::NotStowed => "not stowed",
+            Self::Conflicting => "conflicting",
+            Self::Missing => "missing",
+        })
+    }
+}
+
+/// A file or a directory that is placed in the target as a whole.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub state:  FileState,
+    /// Why the file conflicts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for FileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.state, self.target.display())?;
+
+        match &self.reason {
+            Some(reason) => write!(f, " ({reason})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State of a package in the target directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageReport {
+    pub package:     PathBuf,
+    pub target:      PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub state:       PackageState,
+    pub files:       Vec<FileReport>,
+    /// Paths that could not be checked.
+    pub errors:      Vec<String>,
+}
+
+impl fmt::Display for PackageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.package.display(), self.state)
+    }
+}
+
+/// Outcome of [`Stower::run`](crate::Stower::run).
+#[derive(Debug)]
+pub struct RunReport {
+    pub status:    Status,
+    /// Changes that were applied, or would be applied in a simulation. Empty if
+    /// nothing is changed. Symlinks hold the path they contain.
+    pub actions:   Vec<Action>,
+    /// Hooks that ran, or would run in a simulation.
+    pub hooks:     Vec<Hook>,
+    /// Paths in the way. Nothing is changed if there are any.
+    pub conflicts: Vec<Error>,
+    /// Paths that are left as they are.
+    pub skipped:   Vec<Skip>,
+    pub errors:    Vec<Error>,
+    /// States of the packages, when only the status is asked.
+    pub packages:  Vec<PackageReport>,
+}
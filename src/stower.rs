@@ -1,21 +1,51 @@
 use std::{
+    collections::{HashMap, HashSet},
     env,
-    fs,
-    io::{self, Result},
-    os::unix::{self, fs::MetadataExt},
-    path::{Path, PathBuf},
+    io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        LazyLock,
+        Once,
+    },
 };
 
 use regex::Regex;
+use serde::Deserialize;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    flag,
+    low_level,
+};
 
-use crate::cmd::Args;
-
-// TODO: make simulate keep trck of changes so it will generate more realistic
-// simulation
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    filesystem::{Filesystem, RealFs},
+    hook::{Hook, Operation, When},
+    ignore::{IgnoreList, DEFAULT_IGNORE, IGNORED_PACKAGES, IGNORE_FILE},
+    journal::Journal,
+    keyword::{self, Keyword},
+    manifest::{Manifest, MANIFEST_FILE, PACKAGE_DIR},
+    plan::{Action, Plan},
+    report::{FileReport, FileState, PackageReport, PackageState, RunReport},
+    template::{self, TEMPLATE_EXTENSION},
+};
 
 macro_rules! print_verbose {
     ($self:ident, $($arg:tt)*) => {
-        if $self.verbose || $self.simulate {
+        // Machine-readable output is kept clean
+        if ($self.verbose || $self.simulate) && !$self.json && !$self.quiet {
+            println!($($arg)*);
+        }
+    };
+}
+
+macro_rules! print_message {
+    ($self:ident, $($arg:tt)*) => {
+        if !$self.quiet {
             println!($($arg)*);
         }
     };
@@ -23,7 +53,10 @@ macro_rules! print_verbose {
 
 macro_rules! prompt {
     ($self:ident, $default:expr, $($arg:tt)*) => {
-        {
+        // Questions are answered with no when nobody can answer them
+        if !$self.interactive {
+            false
+        } else {
             use std::io::{self, Write};
 
             print!("{} {}: ", format!($($arg)*), if $default { "[Y/n]" } else { "[y/N]" });
@@ -42,50 +75,731 @@ macro_rules! prompt {
     };
 }
 
-pub struct Stower {
-    #[allow(dead_code)]
+/// Whether no changes are being applied. Interrupt signals only stop the
+/// program then.
+static IDLE: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(true)));
+static DEFAULT_ACTIONS: Once = Once::new();
+
+type ActionFunc<F> = fn(&Stower<F>, &mut Plan<'_, F>, &Path, &Path, bool) -> Result<()>;
+type ExtraFunc<F> = fn(&Stower<F>, &mut Plan<'_, F>, &Path) -> Result<()>;
+
+/// Outcome of a run. Used as the exit code of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Everything is done.
+    Success          = 0,
+    /// Something unexpected happened, nothing is changed.
+    Error            = 1,
+    /// A package, a path or an argument is invalid, nothing is changed.
+    InvalidInput     = 2,
+    /// Some paths are in the way, nothing is changed.
+    Conflict         = 3,
+    /// Not enough permissions to read or change a path, nothing is changed.
+    PermissionDenied = 4,
+    /// Changes are made but some paths are skipped because of errors or
+    /// conflicts.
+    PartialSuccess   = 5,
+}
+
+impl Status {
+    pub fn code(self) -> i32 { self as i32 }
+
+    pub fn from_error(error: &Error) -> Self {
+        match error {
+            Error::Conflict { .. } | Error::NotOwned { .. } => Self::Conflict,
+            Error::SecurityCheckFailed { .. }
+            | Error::KeywordNotAllowed { .. }
+            | Error::PermissionDenied { .. } => Self::PermissionDenied,
+            Error::InvalidRegex { .. }
+            | Error::InvalidName { .. }
+            | Error::MissingVariable { .. }
+            | Error::InvalidTemplate { .. }
+            | Error::CrossDevice { .. }
+            | Error::InvalidConfig { .. } => Self::InvalidInput,
+            Error::HookFailed { .. } => Self::Error,
+            Error::Io { error, .. } =>
+                match error.kind() {
+                    io::ErrorKind::InvalidInput | io::ErrorKind::NotFound => Self::InvalidInput,
+                    _ => Self::Error,
+                },
+        }
+    }
+}
+
+/// What to do when a path is in the way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Report every conflict and change nothing.
+    #[default]
+    Abort,
+    /// Leave the conflicting paths as they are and change the others.
+    Skip,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!("unknown conflict policy: {value}")),
+        }
+    }
+}
+
+/// How files in a package are placed in the target directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    #[default]
+    Symlink,
+    /// Copies the files. Directories are created instead of linked.
+    Copy,
+    /// Hard links the files. Directories are created instead of linked. The
+    /// package and the target must be on the same filesystem.
+    HardLink,
+}
+
+/// Stows, unstows, restows and adopts packages, or reports their status.
+/// Created with [`StowerBuilder`].
+pub struct Stower<F: Filesystem = RealFs> {
+    fs:             F,
     stow_dir:       PathBuf,
     target_dir:     PathBuf,
     simulate:       bool,
     verbose:        bool,
+    interactive:    bool,
+    strict:         bool,
+    on_conflict:    ConflictPolicy,
     special_paths:  bool,
     security_check: bool,
+    dotfiles:       bool,
+    templates:      bool,
+    mode:           LinkMode,
+    absolute_links: bool,
+    json:           bool,
+    quiet:          bool,
+    replace_name:   Option<(Regex, String)>,
+    /// User-defined special keywords by name, without `@`.
+    keywords:       HashMap<String, Keyword>,
+    /// Built-in and custom template variables by name.
+    variables:      HashMap<String, String>,
+    /// Ignore lists of the packages that have their own ignore file.
+    ignore:         HashMap<PathBuf, IgnoreList>,
+    /// Ignore list of the other packages.
+    default_ignore: IgnoreList,
+    /// Files that are always copied, for the packages that list them in
+    /// their manifest.
+    copy:           HashMap<PathBuf, IgnoreList>,
+    /// Manifests of the packages by name. Their target directories are
+    /// resolved.
+    manifests:      HashMap<PathBuf, Manifest>,
+    stow:           Vec<PathBuf>,
+    unstow:         Vec<PathBuf>,
+    restow:         Vec<PathBuf>,
+    adopt:          Vec<PathBuf>,
+    /// Packages to report the status of. `None` if the status is not asked.
+    status:         Option<Vec<PathBuf>>,
+    /// Given packages that are not a directory in the stow directory.
+    missing:        Vec<PathBuf>,
+}
+
+impl Stower<RealFs> {
+    /// Starts building a [`Stower`] that works on the real filesystem.
+    pub fn builder() -> StowerBuilder<RealFs> { StowerBuilder::new() }
+}
+
+/// Builder for [`Stower`]. Defaults are the same as the command line: the
+/// current directory is the stow directory, its parent is the target
+/// directory and special paths and security checks are enabled. Unlike the
+/// command line, it never asks questions unless [`Self::interactive`] is set.
+pub struct StowerBuilder<F: Filesystem = RealFs> {
+    fs:             F,
+    stow_dir:       PathBuf,
+    /// Parent of the stow directory if not set.
+    target_dir:     Option<PathBuf>,
+    simulate:       bool,
+    verbose:        bool,
+    interactive:    bool,
+    strict:         bool,
+    on_conflict:    ConflictPolicy,
+    special_paths:  bool,
+    security_check: bool,
+    dotfiles:       bool,
+    include_hidden: bool,
+    templates:      bool,
+    mode:           LinkMode,
+    absolute_links: bool,
+    json:           bool,
+    quiet:          bool,
     replace_name:   Option<(String, String)>,
+    keywords:       HashMap<String, Keyword>,
+    variables:      HashMap<String, String>,
+    ignore:         Vec<String>,
+    tags:           Vec<String>,
     stow:           Vec<PathBuf>,
     unstow:         Vec<PathBuf>,
     restow:         Vec<PathBuf>,
     adopt:          Vec<PathBuf>,
+    status:         Vec<PathBuf>,
 }
 
-impl Stower {
-    pub fn new(options: Args) -> Result<Self> {
-        let full_stow_path = fs::canonicalize(&options.stow_dir)?;
-        let full_target_path = fs::canonicalize(&options.target_dir)?;
-
-        Ok(Self {
-            stow_dir:       full_stow_path.clone(),
-            target_dir:     full_target_path,
-            simulate:       options.simulate,
-            verbose:        options.verbose,
-            special_paths:  !options.no_special_paths,
-            security_check: !options.no_security_check,
-            replace_name:   if options.replace_name.is_empty() {
-                None
-            } else {
-                Some((
-                    options.replace_name[0].clone(),
-                    options.replace_name[1].clone(),
-                ))
+impl StowerBuilder<RealFs> {
+    pub fn new() -> Self {
+        Self {
+            fs:             RealFs,
+            stow_dir:       PathBuf::from("."),
+            target_dir:     None,
+            simulate:       false,
+            verbose:        false,
+            interactive:    false,
+            strict:         false,
+            on_conflict:    ConflictPolicy::Abort,
+            special_paths:  true,
+            security_check: true,
+            dotfiles:       false,
+            include_hidden: false,
+            templates:      true,
+            mode:           LinkMode::Symlink,
+            absolute_links: false,
+            json:           false,
+            quiet:          false,
+            replace_name:   None,
+            keywords:       HashMap::new(),
+            variables:      HashMap::new(),
+            ignore:         Vec::new(),
+            tags:           Vec::new(),
+            stow:           Vec::new(),
+            unstow:         Vec::new(),
+            restow:         Vec::new(),
+            adopt:          Vec::new(),
+            status:         Vec::new(),
+        }
+    }
+}
+
+impl Default for StowerBuilder<RealFs> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<F: Filesystem> StowerBuilder<F> {
+    /// Makes every filesystem operation go through `fs`.
+    pub fn fs<G: Filesystem>(self, fs: G) -> StowerBuilder<G> {
+        StowerBuilder {
+            fs,
+            stow_dir: self.stow_dir,
+            target_dir: self.target_dir,
+            simulate: self.simulate,
+            verbose: self.verbose,
+            interactive: self.interactive,
+            strict: self.strict,
+            on_conflict: self.on_conflict,
+            special_paths: self.special_paths,
+            security_check: self.security_check,
+            dotfiles: self.dotfiles,
+            include_hidden: self.include_hidden,
+            templates: self.templates,
+            mode: self.mode,
+            absolute_links: self.absolute_links,
+            json: self.json,
+            quiet: self.quiet,
+            replace_name: self.replace_name,
+            keywords: self.keywords,
+            variables: self.variables,
+            ignore: self.ignore,
+            tags: self.tags,
+            stow: self.stow,
+            unstow: self.unstow,
+            restow: self.restow,
+            adopt: self.adopt,
+            status: self.status,
+        }
+    }
+
+    pub fn stow_dir(mut self, stow_dir: impl Into<PathBuf>) -> Self {
+        self.stow_dir = stow_dir.into();
+        self
+    }
+
+    pub fn target_dir(mut self, target_dir: impl Into<PathBuf>) -> Self {
+        self.target_dir = Some(target_dir.into());
+        self
+    }
+
+    pub fn simulate(mut self, simulate: bool) -> Self {
+        self.simulate = simulate;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Asks on stdin whether files in the way should be replaced. Otherwise
+    /// they are conflicts, as if the answer was no.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn special_paths(mut self, special_paths: bool) -> Self {
+        self.special_paths = special_paths;
+        self
+    }
+
+    pub fn security_check(mut self, security_check: bool) -> Self {
+        self.security_check = security_check;
+        self
+    }
+
+    /// Maps the `dot-` prefix of file names in packages to `.`, like GNU
+    /// Stow's `--dotfiles`.
+    pub fn dotfiles(mut self, dotfiles: bool) -> Self {
+        self.dotfiles = dotfiles;
+        self
+    }
+
+    /// Allows packages whose names start with `.`. Version control directories
+    /// like `.git` are still not packages.
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Renders files ending with `.tmpl` instead of linking them.
+    pub fn templates(mut self, templates: bool) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// How files are placed in the target. Package manifests can override it.
+    pub fn mode(mut self, mode: LinkMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Creates symlinks with absolute paths instead of paths relative to the
+    /// link, which GNU Stow creates.
+    pub fn absolute_links(mut self, absolute_links: bool) -> Self {
+        self.absolute_links = absolute_links;
+        self
+    }
+
+    /// Prints the status of the packages as JSON.
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Prints nothing, not even the summary. The outcome is only given by the
+    /// [`RunReport`] of [`Stower::run`].
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Replaces every match of the `find` regex in file names with
+    /// `replace`.
+    pub fn replace_name(mut self, find: impl Into<String>, replace: impl Into<String>) -> Self {
+        self.replace_name = Some((find.into(), replace.into()));
+        self
+    }
+
+    /// Applies the settings set in `config`. Ignore patterns and keywords are
+    /// added to the existing ones.
+    pub fn config(mut self, config: Config) -> Self {
+        self.stow_dir = config.stow_dir.unwrap_or(self.stow_dir);
+        self.target_dir = config.target_dir.or(self.target_dir);
+        self.verbose = config.verbose.unwrap_or(self.verbose);
+        self.strict = config.strict.unwrap_or(self.strict);
+        self.on_conflict = config.on_conflict.unwrap_or(self.on_conflict);
+        self.special_paths = config.special_paths.unwrap_or(self.special_paths);
+        self.security_check = config.security_check.unwrap_or(self.security_check);
+        self.dotfiles = config.dotfiles.unwrap_or(self.dotfiles);
+        self.include_hidden = config.include_hidden.unwrap_or(self.include_hidden);
+        self.templates = config.templates.unwrap_or(self.templates);
+        self.mode = config.mode.unwrap_or(self.mode);
+        self.absolute_links = config.absolute_links.unwrap_or(self.absolute_links);
+        self.replace_name = config.replace_name.or(self.replace_name);
+        self.ignore.extend(config.ignore);
+        self.variables.extend(config.variables);
+
+        config
+            .keywords
+            .into_iter()
+            .fold(self, |builder, (name, keyword)| {
+                builder.keyword(name, keyword)
+            })
+    }
+
+    /// Adds a special keyword. `name` may start with `@`. Built-in keywords
+    /// cannot be redefined, keywords with their names are never used.
+    pub fn keyword(mut self, name: impl Into<String>, keyword: Keyword) -> Self {
+        let name = name.into();
+        let name = name.strip_prefix('@').unwrap_or(&name).to_string();
+        self.keywords.insert(name, keyword);
+        self
+    }
+
+    /// Adds a template variable. Overrides the built-in variable with the
+    /// same name.
+    pub fn variable(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.variables.insert(name.into(), value.into());
+        self
+    }
+
+    /// Never stows the paths matching one of `patterns`, in addition to the
+    /// ignore files.
+    pub fn ignore<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.ignore.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only handles the packages that have one of `tags` in their manifest.
+    pub fn tags<S: Into<String>>(mut self, tags: impl IntoIterator<Item = S>) -> Self {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn stow<P: Into<PathBuf>>(mut self, packages: impl IntoIterator<Item = P>) -> Self {
+        self.stow.extend(packages.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn unstow<P: Into<PathBuf>>(mut self, packages: impl IntoIterator<Item = P>) -> Self {
+        self.unstow.extend(packages.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn restow<P: Into<PathBuf>>(mut self, packages: impl IntoIterator<Item = P>) -> Self {
+        self.restow.extend(packages.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn adopt<P: Into<PathBuf>>(mut self, packages: impl IntoIterator<Item = P>) -> Self {
+        self.adopt.extend(packages.into_iter().map(Into::into));
+        self
+    }
+
+    /// Reports whether the packages are stowed. Nothing is changed and the
+    /// other packages are ignored if any is given.
+    pub fn status<P: Into<PathBuf>>(mut self, packages: impl IntoIterator<Item = P>) -> Self {
+        self.status.extend(packages.into_iter().map(Into::into));
+        self
+    }
+
+    /// Resolves the directories and validates the options.
+    pub fn build(self) -> Result<Stower<F>> {
+        let fs = self.fs;
+        let full_stow_path = fs
+            .canonicalize(&self.stow_dir)
+            .map_err(|why| Error::io(&self.stow_dir, &self.stow_dir, why))?;
+        let target_dir = match self.target_dir {
+            Some(target_dir) => target_dir,
+            None =>
+                full_stow_path
+                    .parent()
+                    .unwrap_or(&full_stow_path)
+                    .to_path_buf(),
+        };
+        let full_target_path = fs
+            .canonicalize(&target_dir)
+            .map_err(|why| Error::io(&target_dir, &target_dir, why))?;
+
+        let replace_name = match self.replace_name {
+            Some((find, replace)) => {
+                let re = Regex::new(&find).map_err(|why| {
+                    Error::InvalidRegex {
+                        source:  full_stow_path.clone(),
+                        target:  full_target_path.clone(),
+                        pattern: find.clone(),
+                        error:   why,
+                    }
+                })?;
+
+                Some((re, replace))
             },
-            stow:           Self::ready_directories(full_stow_path.clone(), options.stow),
-            unstow:         Self::ready_directories(full_stow_path.clone(), options.unstow),
-            restow:         Self::ready_directories(full_stow_path.clone(), options.restow),
-            adopt:          Self::ready_directories(full_stow_path, options.adopt),
+            None => None,
+        };
+
+        // Checked alone first, so errors in them are not blamed on an ignore file
+        Self::ignore_list(
+            &[],
+            &full_stow_path,
+            &full_target_path,
+            self.ignore.clone(),
+        )?;
+        let global_ignore = Self::read_ignore_file(&fs, &full_stow_path.join(IGNORE_FILE))?
+            .unwrap_or_else(|| DEFAULT_IGNORE.iter().map(ToString::to_string).collect());
+        let default_ignore = Self::ignore_list(
+            &self.ignore,
+            &full_stow_path.join(IGNORE_FILE),
+            &full_target_path,
+            global_ignore,
+        )?;
+
+        let requested = [
+            &self.stow,
+            &self.unstow,
+            &self.restow,
+            &self.adopt,
+            &self.status,
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|package| package.file_name())
+        .collect::<HashSet<_>>();
+
+        let mut ignore = HashMap::new();
+        let mut manifests = HashMap::new();
+        let mut copy = HashMap::new();
+        for package in fs
+            .read_dir(&full_stow_path)
+            .map_err(|why| Error::io(&full_stow_path, &full_target_path, why))?
+        {
+            if !fs.is_dir(&package) {
+                continue;
+            }
+
+            let name = package.file_name().expect("Cannot fail");
+            let (manifest, copy_list, ignore_list) =
+                match Self::read_package(&fs, &package, &self.ignore, &full_target_path) {
+                    Ok(files) => files,
+                    // Other packages are only walked to unfold directories stowed from them, so
+                    // their broken files are left out instead of stopping the run
+                    Err(_) if !requested.contains(name) => continue,
+                    Err(why) => return Err(why),
+                };
+
+            if let Some(copy_list) = copy_list {
+                copy.insert(PathBuf::from(name), copy_list);
+            }
+            if let Some(ignore_list) = ignore_list {
+                ignore.insert(PathBuf::from(name), ignore_list);
+            }
+            manifests.insert(PathBuf::from(name), manifest);
+        }
+
+        let stow = Stower::ready_directories(
+            &fs,
+            &full_stow_path,
+            &self.stow,
+            self.include_hidden,
+            &manifests,
+            &self.tags,
+        );
+        let unstow = Stower::ready_directories(
+            &fs,
+            &full_stow_path,
+            &self.unstow,
+            self.include_hidden,
+            &manifests,
+            &self.tags,
+        );
+        let restow = Stower::ready_directories(
+            &fs,
+            &full_stow_path,
+            &self.restow,
+            self.include_hidden,
+            &manifests,
+            &self.tags,
+        );
+        let adopt = Stower::ready_directories(
+            &fs,
+            &full_stow_path,
+            &self.adopt,
+            self.include_hidden,
+            &manifests,
+            &self.tags,
+        );
+        let status = (!self.status.is_empty()).then(|| {
+            Stower::ready_directories(
+                &fs,
+                &full_stow_path,
+                &self.status,
+                self.include_hidden,
+                &manifests,
+                &self.tags,
+            )
+        });
+        let missing = [self.stow, self.unstow, self.restow, self.adopt, self.status]
+            .into_iter()
+            .flatten()
+            .map(|package| full_stow_path.join(package))
+            .filter(|package| !fs.is_dir(package))
+            .collect();
+
+        for package in [&stow, &unstow, &restow, &adopt]
+            .into_iter()
+            .flatten()
+            .chain(status.iter().flatten())
+        {
+            let name = package.file_name().expect("Cannot fail");
+            let Some(manifest) = manifests.get_mut(Path::new(name)) else {
+                continue;
+            };
+
+            if let Some(target_dir) = &manifest.target_dir {
+                manifest.target_dir = Some(Self::package_target(
+                    &fs,
+                    package,
+                    &full_target_path,
+                    target_dir,
+                )?);
+            }
+        }
+
+        if self.mode == LinkMode::HardLink {
+            let device = |path: &Path| {
+                fs.metadata(path)
+                    .map(|metadata| metadata.dev)
+                    .map_err(|why| Error::io(path, path, why))
+            };
+
+            if device(&full_stow_path)? != device(&full_target_path)? {
+                return Err(Error::CrossDevice {
+                    source: full_stow_path,
+                    target: full_target_path,
+                });
+            }
+        }
+
+        let mut variables = template::builtin_variables(&fs);
+        variables.extend(self.variables);
+
+        Ok(Stower {
+            fs,
+            stow_dir: full_stow_path,
+            target_dir: full_target_path,
+            simulate: self.simulate,
+            verbose: self.verbose,
+            interactive: self.interactive,
+            strict: self.strict,
+            on_conflict: self.on_conflict,
+            special_paths: self.special_paths,
+            security_check: self.security_check,
+            dotfiles: self.dotfiles,
+            templates: self.templates,
+            mode: self.mode,
+            absolute_links: self.absolute_links,
+            json: self.json,
+            quiet: self.quiet,
+            replace_name,
+            keywords: self.keywords,
+            variables,
+            ignore,
+            default_ignore,
+            copy,
+            manifests,
+            stow,
+            unstow,
+            restow,
+            adopt,
+            status,
+            missing,
+        })
+    }
+
+    /// Patterns in the ignore file at `path`, if there is one.
+    fn read_ignore_file(fs: &F, path: &Path) -> Result<Option<Vec<String>>> {
+        match fs.read(path) {
+            Ok(contents) =>
+                Ok(Some(IgnoreList::parse(&String::from_utf8_lossy(
+                    &contents,
+                )))),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(Error::io(path, path, why)),
+        }
+    }
+
+    /// Manifest, copy list and ignore list of `package`. `extra` are the
+    /// ignore patterns given to the builder.
+    fn read_package(
+        fs: &F,
+        package: &Path,
+        extra: &[String],
+        target: &Path,
+    ) -> Result<(Manifest, Option<IgnoreList>, Option<IgnoreList>)> {
+        let manifest = Manifest::load(fs, package)?;
+        let copy = if manifest.copy.is_empty() {
+            None
+        } else {
+            Some(Self::ignore_list(
+                &[],
+                &package.join(PACKAGE_DIR).join(MANIFEST_FILE),
+                target,
+                manifest.copy.clone(),
+            )?)
+        };
+
+        let ignore_file = package.join(IGNORE_FILE);
+        let ignore = Self::read_ignore_file(fs, &ignore_file)?
+            .map(|patterns| Self::ignore_list(extra, &ignore_file, target, patterns))
+            .transpose()?;
+
+        Ok((manifest, copy, ignore))
+    }
+
+    /// Canonical path of the target directory in the manifest of `package`.
+    fn package_target(fs: &F, package: &Path, target: &Path, target_dir: &Path) -> Result<PathBuf> {
+        let path = package.join(PACKAGE_DIR).join(MANIFEST_FILE);
+        let target_dir = keyword::expand(&target_dir.to_string_lossy()).map_err(|name| {
+            Error::InvalidConfig {
+                source: path.clone(),
+                target: path.clone(),
+                error:  format!("environment variable {name} is not set"),
+            }
+        })?;
+        let target_dir = target.join(target_dir);
+
+        fs.canonicalize(&target_dir)
+            .map_err(|why| Error::io(package, &target_dir, why))
+    }
+
+    /// Compiles `patterns` together with `extra`, the ones given to the
+    /// builder.
+    fn ignore_list(
+        extra: &[String],
+        file: &Path,
+        target: &Path,
+        patterns: Vec<String>,
+    ) -> Result<IgnoreList> {
+        let patterns = [extra.to_vec(), patterns].concat();
+
+        IgnoreList::new(&patterns).map_err(|(pattern, why)| {
+            Error::InvalidRegex {
+                source: file.to_path_buf(),
+                target: target.to_path_buf(),
+                pattern,
+                error: why,
+            }
         })
     }
+}
 
-    fn ready_directories(stow_dir: PathBuf, dirs: Vec<PathBuf>) -> Vec<PathBuf> {
-        dirs.into_iter()
+impl<F: Filesystem> Stower<F> {
+    fn ready_directories(
+        fs: &F,
+        stow_dir: &Path,
+        dirs: &[PathBuf],
+        include_hidden: bool,
+        manifests: &HashMap<PathBuf, Manifest>,
+        tags: &[String],
+    ) -> Vec<PathBuf> {
+        dirs.iter()
             .filter(|package| {
                 // The only resons this may fail is given 'filesystem root', '.', or '..' and we
                 // do not want them
@@ -95,151 +809,771 @@ impl Stower {
 
                 // Reasons to not include dot files is that they should be hidden and some files
                 // like `.git` gets in the way when user does `rustow -S *`
-                !name.to_string_lossy().starts_with('.')
+                let name = name.to_string_lossy();
+                if include_hidden {
+                    !IGNORED_PACKAGES.contains(&name.as_ref())
+                } else {
+                    !name.starts_with('.')
+                }
             })
             .map(|package| stow_dir.join(package))
-            .filter(|package| package.is_dir())
+            .filter(|package| fs.is_dir(package))
+            .filter(|package| {
+                let name = package.file_name().expect("Cannot fail");
+                manifests
+                    .get(Path::new(name))
+                    .is_none_or(|manifest| manifest.has_tag(tags))
+            })
             .collect()
     }
 
-    pub fn run(self) {
+    /// Applies the given operations, or only finds the status of the packages.
+    /// Nothing is changed if there is any conflict.
+    pub fn run(self) -> RunReport {
+        if let Some(packages) = &self.status {
+            return self.report_status(packages);
+        }
+
+        let mut plan = Plan::new(&self.fs);
+
+        // Non-package arguments are expected with `rustow -S *`, so they are only an
+        // error in strict mode
+        if self.strict {
+            self.missing.iter().for_each(|package| {
+                plan.error(Error::io(
+                    package,
+                    &self.target_dir,
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "Package is not a directory",
+                    ),
+                ));
+            });
+        }
+
         self.unstow.iter().for_each(|package| {
-            self.handle_directory(
+            if !self.is_ready(&mut plan, package) {
+                return;
+            }
+            self.plan_hooks(&mut plan, package, Operation::Unstow);
+
+            self.handle_package(
+                &mut plan,
                 package,
-                &self.target_dir,
                 Self::unstow,
                 Some(Self::unstow_extra),
-                self.special_paths,
-            )
-            .ok();
+            );
         });
 
         self.restow.iter().for_each(|package| {
-            self.handle_directory(
+            if !self.is_ready(&mut plan, package) {
+                return;
+            }
+            self.plan_hooks(&mut plan, package, Operation::Restow);
+
+            self.handle_package(
+                &mut plan,
                 package,
-                &self.target_dir,
                 Self::unstow,
                 Some(Self::unstow_extra),
-                self.special_paths,
-            )
-            .ok();
-            self.handle_directory(
-                package,
-                &self.target_dir,
-                Self::stow,
-                None,
-                self.special_paths,
-            )
-            .ok();
+            );
+            self.handle_package(&mut plan, package, Self::stow, None);
         });
 
         self.stow.iter().for_each(|package| {
-            self.handle_directory(
-                package,
-                &self.target_dir,
-                Self::stow,
-                None,
-                self.special_paths,
-            )
-            .ok();
+            if !self.is_ready(&mut plan, package) {
+                return;
+            }
+            self.plan_hooks(&mut plan, package, Operation::Stow);
+
+            self.handle_package(&mut plan, package, Self::stow, None);
         });
 
         self.adopt.iter().for_each(|package| {
-            self.handle_directory(
-                package,
-                &self.target_dir,
-                Self::adopt,
-                None,
-                self.special_paths,
+            if !self.is_ready(&mut plan, package) {
+                return;
+            }
+            self.plan_hooks(&mut plan, package, Operation::Adopt);
+
+            self.handle_package(&mut plan, package, Self::adopt, None);
+            self.handle_package(&mut plan, package, Self::stow, None);
+        });
+
+        let (status, actions, hooks) = if self.strict && plan.has_problems() {
+            print_message!(
+                self,
+                "Strict mode is enabled. No changes are made."
+            );
+            (
+                Self::planning_status(&plan),
+                Vec::new(),
+                Vec::new(),
             )
-            .ok();
-            self.handle_directory(
+        } else if !plan.conflicts().is_empty() {
+            print_message!(
+                self,
+                "Found {} conflict(s). No changes are made:",
+                plan.conflicts().len()
+            );
+            plan.conflicts()
+                .iter()
+                .for_each(|conflict| print_message!(self, "  {conflict}"));
+
+            (Status::Conflict, Vec::new(), Vec::new())
+        } else {
+            let result = if self.simulate {
+                Ok(())
+            } else {
+                self.run_pre_hooks(&mut plan)
+                    .and_then(|()| self.execute(&mut plan))
+                    .inspect(|()| self.run_post_hooks(&mut plan))
+            };
+            // Hooks are only run for real, so simulations give the ones that would run
+            let hooks = if self.simulate {
+                plan.hooks().to_vec()
+            } else {
+                plan.hooks_run().to_vec()
+            };
+
+            match result {
+                Ok(()) => {
+                    let actions = plan
+                        .actions()
+                        .iter()
+                        .map(|action| self.resolve(&plan, action))
+                        .collect();
+                    let status = if plan.has_problems() {
+                        Status::PartialSuccess
+                    } else {
+                        Status::Success
+                    };
+
+                    (status, actions, hooks)
+                },
+                Err(why) => {
+                    let status = Status::from_error(&why);
+                    plan.error(why);
+                    (status, Vec::new(), hooks)
+                },
+            }
+        };
+
+        let report = plan.into_report(status, actions, hooks);
+        if !self.quiet {
+            self.print_summary(&report);
+        }
+
+        report
+    }
+
+    /// Hooks in `plan` that run at `when`.
+    fn hooks<'p>(plan: &'p Plan<'_, F>, when: When) -> impl Iterator<Item = &'p Hook> {
+        plan.hooks().iter().filter(move |hook| hook.when == when)
+    }
+
+    /// Runs the hooks before the changes. Stops at the first failing one, so
+    /// nothing is changed.
+    fn run_pre_hooks(&self, plan: &mut Plan<'_, F>) -> Result<()> {
+        let hooks = Self::hooks(plan, When::Pre).cloned().collect::<Vec<_>>();
+
+        hooks.iter().try_for_each(|hook| {
+            print_verbose!(self, "{hook}");
+            hook.run(&self.stow_dir)?;
+            plan.hook_run(hook.clone());
+
+            Ok(())
+        })
+    }
+
+    /// Runs the hooks after the changes. The changes are kept if one fails.
+    fn run_post_hooks(&self, plan: &mut Plan<'_, F>) {
+        let hooks = Self::hooks(plan, When::Post).cloned().collect::<Vec<_>>();
+
+        hooks.iter().for_each(|hook| {
+            print_verbose!(self, "{hook}");
+            match hook.run(&self.stow_dir) {
+                Ok(()) => plan.hook_run(hook.clone()),
+                Err(why) => plan.error(why),
+            }
+        });
+    }
+
+    fn print_summary(&self, report: &RunReport) {
+        let hooks = |when: When| report.hooks.iter().filter(move |hook| hook.when == when);
+        let count =
+            |func: fn(&Action) -> bool| report.actions.iter().filter(|action| func(action)).count();
+
+        if self.simulate {
+            hooks(When::Pre).for_each(|hook| println!("{hook}"));
+            report
+                .actions
+                .iter()
+                .for_each(|action| println!("{action}"));
+            hooks(When::Post).for_each(|hook| println!("{hook}"));
+        }
+
+        println!(
+            "{}: {} link(s) created, {} file(s) rendered, {} copied, {} removed, {} hook(s) {}, {} \
+             skipped, {} error(s)",
+            if self.simulate {
+                "Simulation summary"
+            } else {
+                "Summary"
+            },
+            count(|action| {
+                matches!(
+                    action,
+                    Action::CreateSymlink { .. } | Action::CreateHardLink { .. }
+                )
+            }),
+            count(|action| matches!(action, Action::WriteFile { .. })),
+            count(|action| matches!(action, Action::CopyFile { .. })),
+            count(|action| {
+                matches!(
+                    action,
+                    Action::RemoveSymlink(_) | Action::RemoveFile(_)
+                )
+            }),
+            report.hooks.len(),
+            if self.simulate { "to run" } else { "run" },
+            report.skipped.len(),
+            report.errors.len(),
+        );
+
+        report
+            .errors
+            .iter()
+            .for_each(|why| println!("  Error: {why}"));
+        report
+            .skipped
+            .iter()
+            .filter(|skip| skip.conflict || self.verbose)
+            .for_each(|skip| {
+                println!(
+                    "  Skipped {}: {}",
+                    skip.path.display(),
+                    skip.reason
+                );
+            });
+    }
+
+    /// Finds whether each of `packages` is stowed. Nothing is changed.
+    fn report_status(&self, packages: &[PathBuf]) -> RunReport {
+        let mut status = if self.missing.is_empty() {
+            Status::Success
+        } else {
+            Status::InvalidInput
+        };
+        let missing = self.missing.iter().map(|package| {
+            PackageReport {
+                package:     PathBuf::from(package.file_name().unwrap_or(package.as_os_str())),
+                target:      self.target_dir.clone(),
+                description: None,
+                state:       PackageState::Missing,
+                files:       Vec::new(),
+                errors:      Vec::new(),
+            }
+        });
+        let packages = packages
+            .iter()
+            .map(|package| {
+                let mut plan = Plan::new(&self.fs);
+                self.handle_package(&mut plan, package, Self::status, None);
+
+                if status == Status::Success && !plan.errors().is_empty() {
+                    status = Self::planning_status(&plan);
+                }
+
+                PackageReport {
+                    package:     PathBuf::from(package.file_name().expect("Cannot fail")),
+                    target:      self.target_of(package).to_path_buf(),
+                    description: self
+                        .manifest(package)
+                        .and_then(|manifest| manifest.description.clone()),
+                    state:       PackageState::of(plan.reports()),
+                    files:       plan.reports().to_vec(),
+                    errors:      plan.errors().iter().map(ToString::to_string).collect(),
+                }
+            })
+            .chain(missing)
+            .collect();
+
+        let mut report = RunReport {
+            status,
+            actions: Vec::new(),
+            hooks: Vec::new(),
+            conflicts: Vec::new(),
+            skipped: Vec::new(),
+            errors: Vec::new(),
+            packages,
+        };
+
+        if !self.quiet {
+            if let Err(why) = self.print_status(&report) {
+                println!("Cannot print the status as JSON: {why}");
+                report.status = Status::Error;
+            }
+        }
+
+        report
+    }
+
+    /// Prints whether each package of `report` is stowed, with the state of
+    /// every file in verbose mode.
+    fn print_status(&self, report: &RunReport) -> serde_json::Result<()> {
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report.packages)?
+            );
+
+            return Ok(());
+        }
+
+        report.packages.iter().for_each(|package| {
+            println!("{package}");
+
+            if self.verbose {
+                package.files.iter().for_each(|file| println!("  {file}"));
+            }
+            package
+                .errors
+                .iter()
+                .for_each(|why| println!("  Error: {why}"));
+        });
+
+        Ok(())
+    }
+
+    /// Records `conflict` according to the conflict policy.
+    fn conflict(&self, plan: &mut Plan<'_, F>, conflict: Error) {
+        match self.on_conflict {
+            ConflictPolicy::Abort => plan.conflict(conflict),
+            ConflictPolicy::Skip => {
+                print_verbose!(self, "{conflict}. Skipping...");
+
+                let reason = match &conflict {
+                    Error::Conflict { reason, .. } => reason.clone(),
+                    Error::NotOwned { .. } => "not owned by the stow directory".to_string(),
+                    _ => conflict.to_string(),
+                };
+                plan.skip_conflict(conflict.target_path(), reason);
+            },
+        }
+    }
+
+    /// The most important problem found while planning.
+    fn planning_status(plan: &Plan<'_, F>) -> Status {
+        let kinds = plan
+            .errors()
+            .iter()
+            .map(Status::from_error)
+            .collect::<Vec<_>>();
+
+        [
+            Status::PermissionDenied,
+            Status::InvalidInput,
+            Status::Error,
+        ]
+        .into_iter()
+        .find(|status| kinds.contains(status))
+        .unwrap_or(Status::Conflict)
+    }
+
+    /// Whether the environment variables `package` requires or uses in
+    /// `@env:<name>` folders are set. If not, the package is skipped, or it is
+    /// an error in strict mode.
+    fn is_ready(&self, plan: &mut Plan<'_, F>, package: &Path) -> bool {
+        let manifest = self.manifest(package);
+
+        if let Some(description) = manifest.and_then(|manifest| manifest.description.as_ref()) {
+            print_verbose!(self, "{}: {description}", package.display());
+        }
+
+        let Some(name) = manifest
+            .and_then(Manifest::missing_env)
+            .map(ToString::to_string)
+            .or_else(|| self.unset_env_keyword(plan, package))
+        else {
+            return true;
+        };
+
+        if self.strict {
+            plan.error(Error::MissingVariable {
+                source: package.to_path_buf(),
+                target: self.target_of(package).to_path_buf(),
+                name:   name.to_string(),
+            });
+        } else {
+            print_message!(
+                self,
+                "Environment variable {name} is not set. Skipping {}...",
+                package.display()
+            );
+            plan.skip(
                 package,
-                &self.target_dir,
-                Self::stow,
-                None,
-                self.special_paths,
-            )
-            .ok();
+                format!("environment variable {name} is not set"),
+            );
+        }
+
+        false
+    }
+
+    /// Name of an unset environment variable of an `@env:<name>` folder in the
+    /// package directory `dir`.
+    fn unset_env_keyword(&self, plan: &Plan<'_, F>, dir: &Path) -> Option<String> {
+        let entries = plan.fs().read_dir(dir).ok()?;
+
+        entries
+            .iter()
+            .filter(|entry| !self.is_ignored(entry) && !plan.fs().is_symlink(entry))
+            .find_map(|entry| {
+                let unset = self
+                    .is_special_path(entry)
+                    .then(|| entry.file_name()?.to_str()?.strip_prefix("@env:"))
+                    .flatten()
+                    .filter(|name| env::var_os(name).is_none_or(|value| value.is_empty()))
+                    .map(ToString::to_string);
+
+                unset.or_else(|| {
+                    plan.fs()
+                        .is_dir(entry)
+                        .then(|| self.unset_env_keyword(plan, entry))
+                        .flatten()
+                })
+            })
+    }
+
+    /// Plans the hooks of `package` for `operation`.
+    fn plan_hooks(&self, plan: &mut Plan<'_, F>, package: &Path, operation: Operation) {
+        let target = self.target_of(package);
+
+        [When::Pre, When::Post]
+            .into_iter()
+            .filter_map(|when| Hook::find(&self.fs, package, target, operation, when))
+            .for_each(|hook| plan.hook(hook));
+    }
+
+    fn handle_package(
+        &self,
+        plan: &mut Plan<'_, F>,
+        package: &Path,
+        action_func: ActionFunc<F>,
+        extra_func: Option<ExtraFunc<F>>,
+    ) {
+        let target = self.target_of(package);
+        if target != self.target_dir {
+            plan.add_root(target.to_path_buf());
+        }
+
+        if let Err(why) = self.handle_directory(
+            plan,
+            package,
+            target,
+            action_func,
+            extra_func,
+            self.special_paths,
+        ) {
+            plan.error(why);
+        }
+    }
+
+    fn execute(&self, plan: &mut Plan<'_, F>) -> Result<()> {
+        // signal-hook never restores the default actions once a handler is installed,
+        // so they are emulated while nothing is being applied
+        DEFAULT_ACTIONS.call_once(|| {
+            [SIGINT, SIGTERM].into_iter().for_each(|signal| {
+                flag::register_conditional_default(signal, Arc::clone(&IDLE)).ok();
+            });
+        });
+
+        // Let the current action finish and roll back instead of dying in the middle
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let signals = [SIGINT, SIGTERM]
+            .into_iter()
+            .filter_map(|signal| flag::register(signal, Arc::clone(&interrupted)).ok())
+            .collect::<Vec<_>>();
+        IDLE.store(false, Ordering::SeqCst);
+
+        let result = self.apply(plan, &interrupted);
+
+        IDLE.store(true, Ordering::SeqCst);
+        signals.into_iter().for_each(|signal| {
+            low_level::unregister(signal);
+        });
+
+        result
+    }
+
+    /// Applies the actions of `plan`. Everything is rolled back if one fails or
+    /// `interrupted` is set.
+    fn apply(&self, plan: &mut Plan<'_, F>, interrupted: &AtomicBool) -> Result<()> {
+        let mut journal = Journal::new(&self.fs);
+
+        for action in plan.actions().to_vec() {
+            if interrupted.load(Ordering::Relaxed) {
+                print_message!(self, "Interrupted. Rolling back the changes...");
+                self.rollback(plan, journal);
+                let (source, target) = action.paths();
+                return Err(Error::io(
+                    source,
+                    target,
+                    io::Error::new(io::ErrorKind::Interrupted, "Interrupted"),
+                ));
+            }
+
+            print_verbose!(self, "{}", self.resolve(plan, &action));
+
+            let result = match &action {
+                Action::CreateSymlink { original, destination } =>
+                    journal.create_symlink(
+                        &self.link_target(plan, original, destination),
+                        destination,
+                    ),
+                Action::RemoveSymlink(target) => journal.remove_symlink(target),
+                Action::CreateDir(target) => journal.create_dir(target),
+                Action::RemoveDir(target) => journal.remove_dir(target),
+                Action::RemoveFile(target) => journal.remove_file(target),
+                Action::MoveFile { from, to } => journal.move_file(from, to),
+                Action::WriteFile { destination, contents, .. } =>
+                    journal.write_file(destination, contents),
+                Action::CopyFile { original, destination } =>
+                    journal.copy_file(original, destination),
+                Action::CreateHardLink { original, destination } =>
+                    journal.hard_link(original, destination),
+            };
+
+            if let Err(why) = result {
+                print_message!(
+                    self,
+                    "Couldn't apply the changes: {why}. Rolling back the changes..."
+                );
+                self.rollback(plan, journal);
+                let (source, target) = action.paths();
+                return Err(Error::io(source, target, why));
+            }
+        }
+
+        journal.commit();
+
+        Ok(())
+    }
+
+    /// `action` as it is applied, with the path symlinks will contain.
+    fn resolve(&self, plan: &Plan<'_, F>, action: &Action) -> Action {
+        match action {
+            Action::CreateSymlink { original, destination } =>
+                Action::CreateSymlink {
+                    original:    self.link_target(plan, original, destination),
+                    destination: destination.clone(),
+                },
+            action => action.clone(),
+        }
+    }
+
+    /// What the symlink at `destination` points to: `original` itself with
+    /// absolute links, otherwise the path to it from the directory of the link.
+    /// The directory is resolved as it is after `plan`, so simulations show the
+    /// same path.
+    fn link_target(&self, plan: &Plan<'_, F>, original: &Path, destination: &Path) -> PathBuf {
+        let Some(parent) = destination.parent().filter(|_| !self.absolute_links) else {
+            return original.to_path_buf();
+        };
+
+        // The link is resolved from the real directory, not through symlinks
+        let parent = plan
+            .fs()
+            .canonicalize(parent)
+            .unwrap_or_else(|| parent.to_path_buf());
+        let common = parent
+            .components()
+            .zip(original.components())
+            .take_while(|(from, to)| from == to)
+            .count();
+
+        parent
+            .components()
+            .skip(common)
+            .map(|_| Component::ParentDir)
+            .chain(original.components().skip(common))
+            .collect()
+    }
+
+    /// Undoes the changes of `journal`. The changes that cannot be undone are
+    /// errors of `plan`.
+    fn rollback(&self, plan: &mut Plan<'_, F>, journal: Journal<'_, F>) {
+        journal.rollback().into_iter().for_each(|(path, why)| {
+            plan.error(Error::io(&path, &path, why));
         });
     }
 
     fn handle_directory(
         &self,
+        plan: &mut Plan<'_, F>,
         directory: &Path,
         destination: &Path,
-        action_func: fn(&Self, &Path, &Path, bool) -> Result<()>,
-        extra_func: Option<fn(&Self, &Path) -> Result<()>>,
+        action_func: ActionFunc<F>,
+        extra_func: Option<ExtraFunc<F>>,
         use_special_paths: bool,
     ) -> Result<()> {
         let Some(_folder_name) = directory.file_name() else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid directory name",
-            ));
+            return Err(Error::InvalidName {
+                source: directory.to_path_buf(),
+                target: destination.to_path_buf(),
+            });
         };
 
-        let subdirs = fs::read_dir(directory)?;
+        let subdirs = plan
+            .fs()
+            .read_dir(directory)
+            .map_err(|why| Error::io(directory, destination, why))?;
 
         let mut new_destination = destination.to_path_buf();
-        subdirs.filter_map(|e| e.ok()).for_each(|element| {
-            new_destination.push(element.file_name());
-            action_func(
-                self,
-                &element.path(),
-                &new_destination,
-                use_special_paths,
-            )
-            .ok();
-            new_destination.pop();
-        });
+        subdirs
+            .into_iter()
+            .filter(|element| {
+                let is_ignored = self.is_ignored(element);
+                if is_ignored {
+                    print_verbose!(
+                        self,
+                        "{} is ignored. Skipping...",
+                        element.display()
+                    );
+                }
+
+                !is_ignored
+            })
+            .for_each(|element| {
+                new_destination.push(element.file_name().expect("Cannot fail"));
+                if let Err(why) = action_func(
+                    self,
+                    plan,
+                    &element,
+                    &new_destination,
+                    use_special_paths,
+                ) {
+                    plan.error(why);
+                }
+                new_destination.pop();
+            });
 
         if let Some(extra) = extra_func {
-            extra(self, destination).ok();
+            if let Err(why) = extra(self, plan, destination) {
+                plan.error(why);
+            }
         }
 
         Ok(())
     }
 
-    fn stow(&self, original: &Path, destination: &Path, use_special_paths: bool) -> Result<()> {
-        let Some(destination) =
-            self.handle_destination(original, destination, use_special_paths)?
-        else {
-            return Ok(());
-        };
+    fn stow(
+        &self,
+        plan: &mut Plan<'_, F>,
+        original: &Path,
+        destination: &Path,
+        use_special_paths: bool,
+    ) -> Result<()> {
+        let destination =
+            self.handle_destination(plan, original, destination, use_special_paths)?;
 
         let Some(file_name) = original.file_name() else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid file name",
-            ));
+            return Err(Error::InvalidName {
+                source: original.to_path_buf(),
+                target: destination,
+            });
         };
 
-        if original.is_symlink() {
+        if plan.fs().is_symlink(original) {
             print_verbose!(
                 self,
                 "{} is symlink. Skipping...",
                 file_name.to_string_lossy()
             );
+            plan.skip(original, "package file is symlink");
+
+            Ok(())
+        } else if self.is_template(plan, original) {
+            let contents = self.render(original, &destination)?;
+            let is_stowed = plan
+                .fs()
+                .read(&destination)
+                .is_ok_and(|old| old == contents);
+            let action = Action::WriteFile {
+                original: original.to_path_buf(),
+                destination,
+                contents,
+            };
+            self.stow_file(plan, action, is_stowed, "rendered");
+
+            Ok(())
+        } else if self.is_copied(plan, original) {
+            let contents = self
+                .fs
+                .read(original)
+                .map_err(|why| Error::io(original, &destination, why))?;
+            let is_stowed = plan
+                .fs()
+                .read(&destination)
+                .is_ok_and(|old| old == contents);
+            let action = Action::CopyFile {
+                original: original.to_path_buf(),
+                destination,
+            };
+            self.stow_file(plan, action, is_stowed, "copied");
 
             Ok(())
-        } else if destination.is_symlink() {
-            if destination.is_dir() {
-                if let Ok(real_dest) = fs::canonicalize(&destination) {
+        } else if self.is_hard_linked(plan, original) {
+            self.check_device(original, &destination)?;
+
+            let is_stowed = plan.fs().is_hard_link(&destination, original);
+            let action = Action::CreateHardLink {
+                original: original.to_path_buf(),
+                destination,
+            };
+            self.stow_file(plan, action, is_stowed, "hard linked");
+
+            Ok(())
+        } else if plan.fs().is_symlink(&destination) {
+            if plan.fs().is_dir(&destination) {
+                if let Some(real_dest) = plan.fs().canonicalize(&destination) {
                     if real_dest == original {
                         print_verbose!(
                             self,
                             "{} is already stowed. Skipping...",
                             file_name.to_string_lossy()
                         );
+                        plan.skip(&destination, "already stowed");
                         return Ok(());
                     }
 
-                    self.remove_symlink(&destination)?;
-                    self.create_dir(&destination)?;
+                    // Only directories of other packages are unfolded
+                    if !real_dest.starts_with(&self.stow_dir) {
+                        self.conflict(plan, Error::NotOwned {
+                            source: original.to_path_buf(),
+                            target: destination,
+                        });
+                        return Ok(());
+                    }
 
-                    self.handle_directory(&real_dest, &destination, Self::stow, None, false)?;
-                    self.handle_directory(original, &destination, Self::stow, None, false)
+                    plan.push(Action::RemoveSymlink(destination.clone()));
+                    plan.push(Action::CreateDir(destination.clone()));
+
+                    self.handle_directory(
+                        plan,
+                        &real_dest,
+                        &destination,
+                        Self::stow,
+                        None,
+                        use_special_paths,
+                    )?;
+                    self.handle_directory(
+                        plan,
+                        original,
+                        &destination,
+                        Self::stow,
+                        None,
+                        use_special_paths,
+                    )
                 } else {
                     let is_accepted = prompt!(
                         self,
@@ -249,29 +1583,60 @@ impl Stower {
                         );
 
                     if is_accepted {
-                        self.remove_symlink(&destination)?;
-                        self.create_symlink(original, &destination)
+                        plan.push(Action::RemoveSymlink(destination.clone()));
+                        plan.push(Action::CreateSymlink {
+                            original:    original.to_path_buf(),
+                            destination: destination.clone(),
+                        });
                     } else {
-                        print_verbose!(
-                            self,
-                            "{} is already stowed. Skipping...",
-                            file_name.to_string_lossy()
-                        );
-                        Ok(())
+                        self.conflict(plan, Error::Conflict {
+                            source: original.to_path_buf(),
+                            target: destination,
+                            reason: "invalid symlink".to_string(),
+                        });
                     }
+
+                    Ok(())
                 }
-            } else {
+            } else if plan.fs().canonicalize(&destination).as_deref() == Some(original) {
                 print_verbose!(
                     self,
-                    "{} is already symlink. Skipping...",
+                    "{} is already stowed. Skipping...",
                     file_name.to_string_lossy()
                 );
+                plan.skip(&destination, "already stowed");
+
+                Ok(())
+            } else if plan
+                .fs()
+                .canonicalize(&destination)
+                .is_some_and(|real_dest| real_dest.starts_with(&self.stow_dir))
+            {
+                self.conflict(plan, Error::Conflict {
+                    source: original.to_path_buf(),
+                    target: destination,
+                    reason: "already stowed from another package".to_string(),
+                });
+
+                Ok(())
+            } else {
+                self.conflict(plan, Error::NotOwned {
+                    source: original.to_path_buf(),
+                    target: destination,
+                });
 
                 Ok(())
             }
-        } else if destination.exists() {
-            if destination.is_dir() {
-                self.handle_directory(original, &destination, Self::stow, None, false)
+        } else if plan.fs().exists(&destination) {
+            if plan.fs().is_dir(&destination) {
+                self.handle_directory(
+                    plan,
+                    original,
+                    &destination,
+                    Self::stow,
+                    None,
+                    use_special_paths,
+                )
             } else {
                 let is_accepted = prompt!(
                     self,
@@ -281,57 +1646,150 @@ impl Stower {
                 );
 
                 if is_accepted {
-                    self.remove_file(&destination)?;
-                    self.create_symlink(original, &destination)?;
+                    plan.push(Action::RemoveFile(destination.clone()));
+                    plan.push(Action::CreateSymlink {
+                        original:    original.to_path_buf(),
+                        destination: destination.clone(),
+                    });
+                } else {
+                    self.conflict(plan, Error::Conflict {
+                        source: original.to_path_buf(),
+                        target: destination,
+                        reason: "file already exists".to_string(),
+                    });
                 }
 
                 Ok(())
             }
+        } else if plan.fs().is_dir(original)
+            && (self.is_root_dir(plan, &destination) || self.has_special_paths(plan, original))
+        {
+            // Directories of special paths and directories with special paths inside are
+            // never replaced with a symlink
+            if self.is_root_dir(plan, &destination) || self.needs_target_dir(plan, original) {
+                plan.push(Action::CreateDir(destination.clone()));
+            }
+
+            self.handle_directory(
+                plan,
+                original,
+                &destination,
+                Self::stow,
+                None,
+                use_special_paths,
+            )
         } else {
-            self.create_symlink(original, &destination)
+            plan.push(Action::CreateSymlink {
+                original: original.to_path_buf(),
+                destination,
+            });
+
+            Ok(())
         }
     }
 
-    fn unstow(&self, original: &Path, destination: &Path, use_special_paths: bool) -> Result<()> {
-        let Some(destination) =
-            self.handle_destination(original, destination, use_special_paths)?
-        else {
-            return Ok(());
-        };
+    fn unstow(
+        &self,
+        plan: &mut Plan<'_, F>,
+        original: &Path,
+        destination: &Path,
+        use_special_paths: bool,
+    ) -> Result<()> {
+        let destination =
+            self.handle_destination(plan, original, destination, use_special_paths)?;
 
         let Some(file_name) = original.file_name() else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid file name",
-            ));
+            return Err(Error::InvalidName {
+                source: original.to_path_buf(),
+                target: destination,
+            });
         };
 
-        if !destination.exists() {
+        if !plan.fs().exists(&destination) {
+            // Special paths inside may still be stowed
+            if plan.fs().is_dir(original) && self.has_special_paths(plan, original) {
+                return self.handle_directory(
+                    plan,
+                    original,
+                    &destination,
+                    Self::unstow,
+                    Some(Self::unstow_extra),
+                    use_special_paths,
+                );
+            }
+
             print_verbose!(
                 self,
                 "{} does not exist. Skipping...",
                 destination.display()
             );
+            plan.skip(&destination, "does not exist");
             return Ok(());
         }
 
-        if original.is_symlink() {
+        if plan.fs().is_symlink(original) {
             print_verbose!(
                 self,
                 "{} is symlink. Skipping...",
                 file_name.to_string_lossy()
             );
+            plan.skip(original, "package file is symlink");
+
+            Ok(())
+        } else if plan.fs().is_symlink(&destination) {
+            match plan.fs().canonicalize(&destination) {
+                Some(real_dest) if real_dest == original =>
+                    plan.push(Action::RemoveSymlink(destination)),
+                Some(real_dest) if real_dest.starts_with(&self.stow_dir) => {
+                    print_verbose!(
+                        self,
+                        "{} is stowed from another package. Skipping...",
+                        destination.display()
+                    );
+                    plan.skip(&destination, "stowed from another package");
+                },
+                _ =>
+                    self.conflict(plan, Error::NotOwned {
+                        source: original.to_path_buf(),
+                        target: destination,
+                    }),
+            }
+
+            Ok(())
+        } else if self.is_template(plan, original) {
+            let contents = self.render(original, &destination)?;
+            let is_stowed = plan
+                .fs()
+                .read(&destination)
+                .is_ok_and(|old| old == contents);
+            self.unstow_file(plan, &destination, is_stowed, "rendered");
+
+            Ok(())
+        } else if self.is_copied(plan, original) {
+            let contents = self
+                .fs
+                .read(original)
+                .map_err(|why| Error::io(original, &destination, why))?;
+            let is_stowed = plan
+                .fs()
+                .read(&destination)
+                .is_ok_and(|old| old == contents);
+            self.unstow_file(plan, &destination, is_stowed, "copied");
 
             Ok(())
-        } else if destination.is_symlink() {
-            self.remove_symlink(&destination)
-        } else if destination.is_dir() && original.is_dir() {
+        } else if self.is_hard_linked(plan, original) {
+            let is_stowed = plan.fs().is_hard_link(&destination, original);
+            self.unstow_file(plan, &destination, is_stowed, "hard linked");
+
+            Ok(())
+        } else if plan.fs().is_dir(&destination) && plan.fs().is_dir(original) {
             self.handle_directory(
+                plan,
                 original,
                 &destination,
                 Self::unstow,
                 Some(Self::unstow_extra),
-                false, // specail paths only works in first level
+                use_special_paths,
             )
         } else {
             print_verbose!(
@@ -339,199 +1797,602 @@ impl Stower {
                 "{} exists but it is not a symlink. Skipping...",
                 file_name.to_string_lossy()
             );
+            plan.skip_conflict(&destination, "exists but it is not a symlink");
 
             Ok(())
         }
     }
 
-    fn adopt(&self, original: &Path, destination: &Path, use_special_paths: bool) -> Result<()> {
-        let Some(destination) =
-            self.handle_destination(original, destination, use_special_paths)?
-        else {
-            return Ok(());
-        };
+    fn adopt(
+        &self,
+        plan: &mut Plan<'_, F>,
+        original: &Path,
+        destination: &Path,
+        use_special_paths: bool,
+    ) -> Result<()> {
+        let destination =
+            self.handle_destination(plan, original, destination, use_special_paths)?;
 
         let Some(file_name) = original.file_name() else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid file name",
-            ));
+            return Err(Error::InvalidName {
+                source: original.to_path_buf(),
+                target: destination,
+            });
         };
 
-        if !destination.exists() {
+        if !plan.fs().exists(&destination) {
+            // Special paths inside may still be adopted
+            if plan.fs().is_dir(original) && self.has_special_paths(plan, original) {
+                return self.handle_directory(
+                    plan,
+                    original,
+                    &destination,
+                    Self::adopt,
+                    None,
+                    use_special_paths,
+                );
+            }
+
             print_verbose!(
                 self,
                 "{} does not exist. Skipping...",
                 destination.display()
             );
+            plan.skip(&destination, "does not exist");
             return Ok(());
         }
 
-        if destination.is_symlink() {
+        if plan.fs().is_symlink(&destination) {
             print_verbose!(
                 self,
                 "{} is already symlink. Skipping...",
                 destination.display()
             );
+            plan.skip(&destination, "already symlink");
+
+            Ok(())
+        } else if self.is_template(plan, original) {
+            print_verbose!(
+                self,
+                "{} is rendered from a template. Skipping...",
+                destination.display()
+            );
+            plan.skip(&destination, "rendered from a template");
 
             Ok(())
-        } else if original.is_symlink() {
+        } else if plan.fs().is_symlink(original) {
             print_verbose!(
                 self,
                 "{} is symlink but destination is not. Skipping...",
                 file_name.to_string_lossy()
             );
+            plan.skip_conflict(
+                &destination,
+                "package file is symlink but destination is not",
+            );
+
+            Ok(())
+        } else if plan.fs().is_dir(&destination) && plan.fs().is_dir(original) {
+            self.handle_directory(
+                plan,
+                original,
+                &destination,
+                Self::adopt,
+                None,
+                use_special_paths,
+            )
+        } else if plan.fs().is_file(&destination) && plan.fs().is_file(original) {
+            plan.push(Action::MoveFile {
+                from: destination,
+                to:   original.to_path_buf(),
+            });
 
             Ok(())
-        } else if destination.is_dir() && original.is_dir() {
-            self.handle_directory(original, &destination, Self::adopt, None, false)
-        } else if destination.is_file() && original.is_file() {
-            let mut backup_path = PathBuf::from("/tmp/rustow-backup");
-            self.create_dir(&backup_path)?;
-
-            backup_path.push(file_name);
-            self.move_file(original, &backup_path)?;
-            if self.move_file(&destination, original).is_ok() {
-                self.remove_file(&backup_path)
-            } else {
-                self.move_file(&backup_path, original)
-            }
         } else {
             print_verbose!(self, "Original and target are not same type (one is file but other is directory). Skipping...");
+            plan.skip_conflict(
+                &destination,
+                "package file and destination are not same type",
+            );
+
             Ok(())
         }
     }
 
-    fn unstow_extra(&self, target: &Path) -> Result<()> {
-        let mut dir_items = fs::read_dir(target)?;
-        if dir_items.next().is_none() {
-            self.remove_dir(target)?;
+    /// Records whether `original` is in place in the target, the way
+    /// [`Self::stow`] would place it.
+    fn status(
+        &self,
+        plan: &mut Plan<'_, F>,
+        original: &Path,
+        destination: &Path,
+        use_special_paths: bool,
+    ) -> Result<()> {
+        let destination =
+            self.handle_destination(plan, original, destination, use_special_paths)?;
+
+        // Symlinks in packages are never stowed
+        if plan.fs().is_symlink(original) {
+            return Ok(());
         }
 
+        let placed = if self.is_template(plan, original) {
+            let contents = self.render(original, &destination)?;
+            let is_stowed = plan
+                .fs()
+                .read(&destination)
+                .is_ok_and(|old| old == contents);
+
+            Some((is_stowed, "rendered"))
+        } else if self.is_copied(plan, original) {
+            let contents = self
+                .fs
+                .read(original)
+                .map_err(|why| Error::io(original, &destination, why))?;
+            let is_stowed = plan
+                .fs()
+                .read(&destination)
+                .is_ok_and(|old| old == contents);
+
+            Some((is_stowed, "copied"))
+        } else if self.is_hard_linked(plan, original) {
+            Some((
+                plan.fs().is_hard_link(&destination, original),
+                "hard linked",
+            ))
+        } else {
+            None
+        };
+
+        let is_dir = plan.fs().is_dir(original);
+        let (state, reason) = if let Some((is_stowed, done)) = placed {
+            if plan.fs().is_symlink(&destination) || plan.fs().is_dir(&destination) {
+                (
+                    FileState::Conflict,
+                    Some(format!("not a file {done} by Rustow")),
+                )
+            } else if !plan.fs().exists(&destination) {
+                (FileState::NotStowed, None)
+            } else if is_stowed {
+                (FileState::Stowed, None)
+            } else {
+                (
+                    FileState::Conflict,
+                    Some(format!("not the file {done} by Rustow")),
+                )
+            }
+        } else if plan.fs().is_symlink(&destination) {
+            match plan.fs().canonicalize(&destination) {
+                Some(real_dest) if real_dest == original => (FileState::Stowed, None),
+                // Directories stowed from elsewhere are unfolded when stowing
+                Some(_) if is_dir && plan.fs().is_dir(&destination) =>
+                    return self.handle_directory(
+                        plan,
+                        original,
+                        &destination,
+                        Self::status,
+                        None,
+                        use_special_paths,
+                    ),
+                Some(real_dest) if real_dest.starts_with(&self.stow_dir) =>
+                    (
+                        FileState::Conflict,
+                        Some("already stowed from another package".to_string()),
+                    ),
+                Some(_) =>
+                    (
+                        FileState::Conflict,
+                        Some("not owned by the stow directory".to_string()),
+                    ),
+                None =>
+                    (
+                        FileState::Conflict,
+                        Some("invalid symlink".to_string()),
+                    ),
+            }
+        } else if plan.fs().exists(&destination) {
+            if is_dir && plan.fs().is_dir(&destination) {
+                return self.handle_directory(
+                    plan,
+                    original,
+                    &destination,
+                    Self::status,
+                    None,
+                    use_special_paths,
+                );
+            }
+
+            (
+                FileState::Conflict,
+                Some("file already exists".to_string()),
+            )
+        } else if is_dir
+            && (self.is_root_dir(plan, &destination) || self.has_special_paths(plan, original))
+        {
+            // Such directories are created instead of linked, so their contents are checked
+            return self.handle_directory(
+                plan,
+                original,
+                &destination,
+                Self::status,
+                None,
+                use_special_paths,
+            );
+        } else {
+            (FileState::NotStowed, None)
+        };
+
+        plan.report(FileReport {
+            source: original.to_path_buf(),
+            target: destination,
+            state,
+            reason,
+        });
+
         Ok(())
     }
 
-    fn create_symlink(&self, original: &Path, destination: &Path) -> Result<()> {
-        print_verbose!(
-            self,
-            "Creating symlink: {} -> {}",
-            destination.to_string_lossy(),
-            original.to_string_lossy()
-        );
+    /// Removes `target` if unstowing made it empty. Directories that were
+    /// already empty belong to the user and are kept.
+    fn unstow_extra(&self, plan: &mut Plan<'_, F>, target: &Path) -> Result<()> {
+        if self.is_root_dir(plan, target) || !plan.fs().is_dir(target) {
+            return Ok(());
+        }
 
-        if self.simulate {
+        let entries = plan
+            .fs()
+            .read_dir(target)
+            .map_err(|why| Error::io(target, target, why))?;
+
+        if !entries.is_empty() {
             return Ok(());
         }
 
-        unix::fs::symlink(original, destination)
+        // A directory missing from the disk is created in this run
+        let was_empty = self
+            .fs
+            .read_dir(target)
+            .is_ok_and(|entries| entries.is_empty());
+
+        if was_empty {
+            print_verbose!(
+                self,
+                "{} was already empty. Keeping it...",
+                target.display()
+            );
+        } else {
+            plan.push(Action::RemoveDir(target.to_path_buf()));
+        }
+
+        Ok(())
     }
 
-    fn remove_symlink(&self, target: &Path) -> Result<()> {
-        print_verbose!(
-            self,
-            "Removing symlink: {}",
-            target.to_string_lossy()
-        );
+    /// Whether `path` is the target directory or a directory a special path
+    /// points to. These are never removed.
+    fn is_root_dir(&self, plan: &Plan<'_, F>, path: &Path) -> bool {
+        path == self.target_dir
+            || path == Path::new("/")
+            || env::var_os("HOME").is_some_and(|home| path == Path::new(&home))
+            || plan.is_root(path)
+    }
 
-        if self.simulate {
-            return Ok(());
+    /// Whether the name of `path` is a special folder name.
+    fn is_special_path(&self, path: &Path) -> bool {
+        if !self.special_paths {
+            return false;
         }
 
-        fs::remove_file(target)
+        path.file_name().is_some_and(|name| {
+            name.to_string_lossy()
+                .strip_prefix('@')
+                .is_some_and(|name| keyword::is_builtin(name) || self.keywords.contains_key(name))
+        })
     }
 
-    fn create_dir(&self, target: &Path) -> Result<()> {
-        print_verbose!(
-            self,
-            "Creating directory: {}",
-            target.to_string_lossy()
-        );
+    /// Whether `path` is renamed to a dotfile by `--dotfiles`.
+    fn is_dotfile(&self, path: &Path) -> bool {
+        self.dotfiles
+            && path.file_name().is_some_and(|name| {
+                name.to_string_lossy()
+                    .strip_prefix("dot-")
+                    .is_some_and(|rest| !rest.is_empty())
+            })
+    }
 
-        if self.simulate {
-            return Ok(());
-        }
+    /// Whether there is a special folder name, a template, a `dot-` name, or a
+    /// copied or hard linked file anywhere inside the package directory `dir`.
+    /// Such directories cannot be linked as a whole.
+    fn has_special_paths(&self, plan: &Plan<'_, F>, dir: &Path) -> bool {
+        plan.fs().read_dir(dir).is_ok_and(|entries| {
+            entries.iter().any(|entry| {
+                !self.is_ignored(entry)
+                    && (self.is_special_path(entry)
+                        || self.is_dotfile(entry)
+                        || self.is_template(plan, entry)
+                        || self.is_copied(plan, entry)
+                        || self.is_hard_linked(plan, entry)
+                        || (!plan.fs().is_symlink(entry)
+                            && plan.fs().is_dir(entry)
+                            && self.has_special_paths(plan, entry)))
+            })
+        })
+    }
 
-        fs::create_dir_all(target)
+    /// Whether anything inside the package directory `dir` is stowed relative
+    /// to it rather than to a special path, so it needs a directory in the
+    /// target.
+    fn needs_target_dir(&self, plan: &Plan<'_, F>, dir: &Path) -> bool {
+        plan.fs().read_dir(dir).is_ok_and(|entries| {
+            entries.iter().any(|entry| {
+                !self.is_ignored(entry)
+                    && !self.is_special_path(entry)
+                    && (plan.fs().is_symlink(entry)
+                        || !plan.fs().is_dir(entry)
+                        || !self.has_special_paths(plan, entry)
+                        || self.needs_target_dir(plan, entry))
+            })
+        })
     }
 
-    fn remove_dir(&self, target: &Path) -> Result<()> {
-        print_verbose!(
-            self,
-            "Removing directory: {}",
-            target.to_string_lossy()
-        );
+    /// Name of the package `path` is in and the path relative to it.
+    fn split_package<'p>(&self, path: &'p Path) -> Option<(&'p Path, &'p Path)> {
+        let mut components = path.strip_prefix(&self.stow_dir).ok()?.components();
+        let package = components.next()?;
 
-        if self.simulate {
-            return Ok(());
-        }
+        Some((
+            Path::new(package.as_os_str()),
+            components.as_path(),
+        ))
+    }
 
-        fs::remove_dir_all(target)
+    /// Manifest of the package `path` is in.
+    fn manifest(&self, path: &Path) -> Option<&Manifest> {
+        self.split_package(path)
+            .and_then(|(package, _)| self.manifests.get(package))
     }
 
-    fn remove_file(&self, target: &Path) -> Result<()> {
-        print_verbose!(
-            self,
-            "Removing file: {}",
-            target.to_string_lossy()
-        );
+    /// Target directory of the package `path` is in.
+    fn target_of(&self, path: &Path) -> &Path {
+        self.manifest(path)
+            .and_then(|manifest| manifest.target_dir.as_deref())
+            .unwrap_or(&self.target_dir)
+    }
 
-        if self.simulate {
-            return Ok(());
+    /// Whether `path` is a file in a package that is rendered instead of
+    /// linked.
+    fn is_template(&self, plan: &Plan<'_, F>, path: &Path) -> bool {
+        self.templates
+            && path.extension() == Some(TEMPLATE_EXTENSION.as_ref())
+            && path.file_stem().is_some()
+            && !plan.fs().is_symlink(path)
+            && plan.fs().is_file(path)
+    }
+
+    /// Contents of the template `original` rendered for `destination`.
+    fn render(&self, original: &Path, destination: &Path) -> Result<Vec<u8>> {
+        let contents = self
+            .fs
+            .read(original)
+            .map_err(|why| Error::io(original, destination, why))?;
+
+        template::render(
+            &String::from_utf8_lossy(&contents),
+            &self.variables,
+        )
+        .map(String::into_bytes)
+        .map_err(|error| {
+            Error::InvalidTemplate {
+                source: original.to_path_buf(),
+                target: destination.to_path_buf(),
+                error,
+            }
+        })
+    }
+
+    /// Whether `path` is a file in a package that is copied instead of
+    /// linked.
+    fn is_copied(&self, plan: &Plan<'_, F>, path: &Path) -> bool {
+        self.mode_of(path) == LinkMode::Copy
+            && !plan.fs().is_symlink(path)
+            && plan.fs().is_file(path)
+    }
+
+    /// Whether `path` is a file in a package that is hard linked instead of
+    /// symlinked.
+    fn is_hard_linked(&self, plan: &Plan<'_, F>, path: &Path) -> bool {
+        self.mode_of(path) == LinkMode::HardLink
+            && !plan.fs().is_symlink(path)
+            && plan.fs().is_file(path)
+    }
+
+    /// Fails if `destination` would be on another filesystem than `original`,
+    /// where it cannot be a hard link of it.
+    fn check_device(&self, original: &Path, destination: &Path) -> Result<()> {
+        let device = self
+            .fs
+            .metadata(original)
+            .map_err(|why| Error::io(original, destination, why))?
+            .dev;
+        let target_device = destination
+            .ancestors()
+            .skip(1)
+            .find_map(|ancestor| self.fs.metadata(ancestor).ok())
+            .map(|metadata| metadata.dev);
+
+        if target_device.is_some_and(|target_device| target_device != device) {
+            Err(Error::CrossDevice {
+                source: original.to_path_buf(),
+                target: destination.to_path_buf(),
+            })
+        } else {
+            Ok(())
         }
+    }
+
+    /// How `path`, a file in a package, is placed in the target. Files listed
+    /// in the manifest are copied, the others use the mode of the manifest or
+    /// the global one.
+    fn mode_of(&self, path: &Path) -> LinkMode {
+        let Some((package, relative)) = self.split_package(path) else {
+            return self.mode;
+        };
 
-        fs::remove_file(target)
+        if self
+            .copy
+            .get(package)
+            .is_some_and(|list| list.matches(relative))
+        {
+            LinkMode::Copy
+        } else {
+            self.manifests
+                .get(package)
+                .and_then(|manifest| manifest.mode)
+                .unwrap_or(self.mode)
+        }
     }
 
-    fn move_file(&self, original: &Path, destination: &Path) -> Result<()> {
-        print_verbose!(
-            self,
-            "Moving file: {} -> {}",
-            original.to_string_lossy(),
-            destination.to_string_lossy()
-        );
+    /// Applies `action`, which places a regular file in the target, e.g. a
+    /// rendered template or a copy. `is_stowed` tells whether the existing
+    /// file is already the one `action` would place. `done` describes how,
+    /// for messages.
+    fn stow_file(&self, plan: &mut Plan<'_, F>, action: Action, is_stowed: bool, done: &str) {
+        let (original, destination) = action.paths();
+        let (original, destination) = (original.to_path_buf(), destination.to_path_buf());
+
+        if plan.fs().is_symlink(&destination) || plan.fs().is_dir(&destination) {
+            self.conflict(plan, Error::Conflict {
+                source: original,
+                target: destination,
+                reason: format!("not a file {done} by Rustow"),
+            });
+        } else if !plan.fs().exists(&destination) {
+            plan.push(action);
+        } else if is_stowed {
+            print_verbose!(
+                self,
+                "{} is already {done}. Skipping...",
+                destination.display()
+            );
+            plan.skip(&destination, format!("already {done}"));
+        } else {
+            let is_accepted = prompt!(
+                self,
+                false,
+                "{} already exists, would you like to delete it and replace it",
+                destination.display()
+            );
 
-        if self.simulate {
-            return Ok(());
+            if is_accepted {
+                plan.push(Action::RemoveFile(destination));
+                plan.push(action);
+            } else {
+                self.conflict(plan, Error::Conflict {
+                    source: original,
+                    target: destination,
+                    reason: "file already exists".to_string(),
+                });
+            }
         }
+    }
 
-        if fs::rename(original, destination).is_err() {
-            fs::copy(original, destination)?;
-            fs::remove_file(original)?;
+    /// Removes `destination` if it is still the file Rustow placed there,
+    /// which `is_stowed` tells. Changed files are kept and reported.
+    fn unstow_file(&self, plan: &mut Plan<'_, F>, destination: &Path, is_stowed: bool, done: &str) {
+        if !plan.fs().is_file(destination) {
+            print_verbose!(
+                self,
+                "{} is not a file {done} by Rustow. Skipping...",
+                destination.display()
+            );
+            plan.skip_conflict(
+                destination,
+                format!("not a file {done} by Rustow"),
+            );
+        } else if is_stowed {
+            plan.push(Action::RemoveFile(destination.to_path_buf()));
+        } else {
+            print_message!(
+                self,
+                "{} is changed since it was {done}. Skipping...",
+                destination.display()
+            );
+            plan.skip_conflict(
+                destination,
+                format!("changed since it was {done}"),
+            );
         }
+    }
 
-        Ok(())
+    /// Whether `path`, a file in a package, matches the ignore list of its
+    /// package.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some((package, relative)) = self.split_package(path) else {
+            return false;
+        };
+
+        if relative.as_os_str().is_empty() {
+            false
+        } else if relative == Path::new(IGNORE_FILE) || relative.starts_with(PACKAGE_DIR) {
+            true
+        } else {
+            self.ignore
+                .get(package)
+                .unwrap_or(&self.default_ignore)
+                .matches(relative)
+        }
     }
 
-    fn handle_special_paths(&self, original: &Path, destination: &Path) -> Option<PathBuf> {
+    /// Target of `original` if it is a special path, otherwise `destination`.
+    fn handle_special_paths(&self, original: &Path, destination: &Path) -> Result<PathBuf> {
         if !self.special_paths {
-            return Some(destination.to_path_buf());
+            return Ok(destination.to_path_buf());
         }
 
         let Some(file_name) = original.file_name() else {
-            return None;
+            return Err(Error::InvalidName {
+                source: original.to_path_buf(),
+                target: destination.to_path_buf(),
+            });
         };
 
-        match file_name.to_string_lossy().as_ref() {
+        let name = file_name.to_string_lossy();
+        if self.is_special_path(original)
+            && !self
+                .manifest(original)
+                .is_none_or(|manifest| manifest.allows_keyword(&name))
+        {
+            return Err(Error::KeywordNotAllowed {
+                source: original.to_path_buf(),
+                target: destination.to_path_buf(),
+                name:   name.into_owned(),
+            });
+        }
+
+        match name.as_ref() {
             "@home" => {
                 let Ok(home_path) = env::var("HOME") else {
-                    println!("Couldn't find HOME variable.");
-                    return None;
+                    return Err(Error::MissingVariable {
+                        source: original.to_path_buf(),
+                        target: destination.to_path_buf(),
+                        name:   "HOME".to_string(),
+                    });
                 };
 
                 let home_path = PathBuf::from(home_path);
 
-                if home_path.exists() {
-                    Some(home_path)
+                if self.fs.exists(&home_path) {
+                    Ok(home_path)
                 } else {
-                    Some(destination.to_path_buf())
+                    Ok(destination.to_path_buf())
                 }
             },
             "@root" =>
                 if self.is_root_user_file(original) {
-                    Some(PathBuf::from("/"))
+                    Ok(PathBuf::from("/"))
                 } else {
-                    println!(
-                        r#"
+                    if !self.json && !self.quiet {
+                        println!(
+                            r#"
 For security reasons, all the files/folders including and followed by @root file must be owned by root.
 This requred to prevent giving others access to important system files by mistake.
 (Because the owner of the file will be the user who runs Rustow)
@@ -539,25 +2400,79 @@ Also creating a symlink to a path followed by @root generally needs root access
 If you want to stow something inside your home folder, use @home instead.
 Use --no-security-check flag to prevent from this error
 "#
-                    );
+                        );
+                    }
 
-                    None
+                    Err(Error::SecurityCheckFailed {
+                        source: original.to_path_buf(),
+                        target: PathBuf::from("/"),
+                    })
                 },
-            _ => Some(destination.to_path_buf()),
+            name if name.starts_with("@env:") =>
+                self.handle_env(original, destination, &name["@env:".len() ..]),
+            name =>
+                match name.strip_prefix('@').and_then(|name| {
+                    keyword::builtin(name).or_else(|| self.keywords.get(name).cloned())
+                }) {
+                    Some(keyword) => self.handle_keyword(original, destination, &keyword),
+                    None => Ok(destination.to_path_buf()),
+                },
+        }
+    }
+
+    /// Path in the environment variable `name` for `@env:<name>`. Packages
+    /// using unset variables are skipped before they are walked, so it is an
+    /// error here.
+    fn handle_env(&self, original: &Path, destination: &Path, name: &str) -> Result<PathBuf> {
+        match env::var_os(name).filter(|value| !value.is_empty()) {
+            Some(value) => Ok(self.target_of(original).join(value)),
+            None =>
+                Err(Error::MissingVariable {
+                    source: original.to_path_buf(),
+                    target: destination.to_path_buf(),
+                    name:   name.to_string(),
+                }),
         }
     }
 
+    /// Path of a built-in or user-defined keyword. Relative paths are relative
+    /// to the target directory.
+    fn handle_keyword(
+        &self,
+        original: &Path,
+        destination: &Path,
+        keyword: &Keyword,
+    ) -> Result<PathBuf> {
+        let path = keyword.resolve().map_err(|name| {
+            Error::MissingVariable {
+                source: original.to_path_buf(),
+                target: destination.to_path_buf(),
+                name,
+            }
+        })?;
+        let path = self.target_of(original).join(path);
+
+        if keyword.needs_security_check() && !self.is_root_user_file(original) {
+            return Err(Error::SecurityCheckFailed {
+                source: original.to_path_buf(),
+                target: path,
+            });
+        }
+
+        Ok(path)
+    }
+
     fn is_root_user_file(&self, path: &Path) -> bool {
         if !self.security_check {
             return true;
         }
 
         // FIXME: we may not get the metadata because we do not have enough permmissions
-        match path.metadata() {
+        match self.fs.metadata(path) {
             Ok(metadata) =>
-                if metadata.uid() == 0 {
-                    if path.is_dir() {
-                        let subdirs = fs::read_dir(path);
+                if metadata.uid == 0 {
+                    if metadata.is_dir() {
+                        let subdirs = self.fs.read_dir(path);
                         if subdirs.is_err() {
                             return false;
                         }
@@ -565,9 +2480,8 @@ Use --no-security-check flag to prevent from this error
                         // FIXME: we may not read because we do not have enough permmissions
                         subdirs
                             .unwrap()
-                            .filter_map(|e| e.ok())
-                            .map(|element| self.is_root_user_file(&element.path()))
-                            .all(|x| x)
+                            .iter()
+                            .all(|element| self.is_root_user_file(element))
                     } else {
                         true
                     }
@@ -575,42 +2489,51 @@ Use --no-security-check flag to prevent from this error
                     false
                 },
             Err(why) => {
-                dbg!("failed in metadata {}", why);
+                print_verbose!(
+                    self,
+                    "Cannot read the metadata of {}: {why}",
+                    path.display()
+                );
                 false
             },
         }
     }
 
+    /// Path in the target directory for `original`.
     fn handle_destination(
         &self,
+        plan: &mut Plan<'_, F>,
         original: &Path,
         destination: &Path,
         use_special_paths: bool,
-    ) -> Result<Option<PathBuf>> {
-        let mut destination = if use_special_paths {
-            if let Some(path) = self.handle_special_paths(original, destination) {
-                path
-            } else {
-                print_verbose!(
-                    self,
-                    "Got error while handling special paths for {}. Skipping...",
-                    original.display()
-                );
-                return Ok(None);
+    ) -> Result<PathBuf> {
+        if use_special_paths {
+            let special = self.handle_special_paths(original, destination)?;
+
+            // Paths of special folder names are used as they are
+            if special != destination {
+                plan.add_root(special.clone());
+                return Ok(special);
             }
-        } else {
-            destination.to_path_buf()
-        };
+        }
 
-        if let Some((ref find, ref relpace)) = self.replace_name {
-            let Ok(re) = Regex::new(find) else {
-                print_verbose!(self, "Invalid regex: {}", find);
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Invalid regex",
-                ));
-            };
+        let mut destination = destination.to_path_buf();
+
+        if self.is_template(plan, original) {
+            destination.set_extension("");
+        }
+
+        if self.is_dotfile(&destination) {
+            let name = destination
+                .file_name()
+                .expect("Cannot fail")
+                .to_string_lossy()
+                .to_string();
 
+            destination.set_file_name(format!(".{}", &name["dot-".len() ..]));
+        }
+
+        if let Some((ref re, ref relpace)) = self.replace_name {
             let name = destination
                 .file_name()
                 .expect("Cannot fail")
@@ -622,6 +2545,315 @@ Use --no-security-check flag to prevent from this error
             destination.set_file_name(new_name.to_string());
         }
 
-        Ok(Some(destination))
+        Ok(destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFs;
+
+    const STOW_DIR: &str = "/home/dots";
+
+    /// Runs a [`Stower`] for the stow directory `/home/dots` on `fs`.
+    fn run(
+        fs: &MemoryFs,
+        options: impl FnOnce(StowerBuilder<&MemoryFs>) -> StowerBuilder<&MemoryFs>,
+    ) -> Status {
+        options(Stower::builder().fs(fs).stow_dir(STOW_DIR))
+            .quiet(true)
+            .build()
+            .expect("Valid stow directory")
+            .run()
+            .status
+    }
+
+    fn link(fs: &MemoryFs, path: &str) -> PathBuf {
+        fs.read_link(Path::new(path))
+            .unwrap_or_else(|why| panic!("{path} is not a symlink: {why}"))
+    }
+
+    #[test]
+    fn stow_links_files_relative_to_the_link() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "bash").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a"])),
+            Status::Success
+        );
+        assert_eq!(
+            link(&fs, "/home/.bashrc"),
+            Path::new("dots/a/.bashrc")
+        );
+        assert_eq!(fs.contents("/home/.bashrc").unwrap(), b"bash");
+    }
+
+    #[test]
+    fn stow_links_missing_directories_as_a_whole() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.config/app/config", "").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a"])),
+            Status::Success
+        );
+        assert_eq!(
+            link(&fs, "/home/.config"),
+            Path::new("dots/a/.config")
+        );
+    }
+
+    #[test]
+    fn stow_renames_dotfiles_inside_directories() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/dot-config/dot-app/config", "")
+            .unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.dotfiles(true).stow(["a"])),
+            Status::Success
+        );
+        assert!(!fs.is_symlink(Path::new("/home/.config")));
+        assert_eq!(
+            link(&fs, "/home/.config/.app"),
+            Path::new("../dots/a/dot-config/dot-app")
+        );
+
+        assert_eq!(
+            run(&fs, |builder| {
+                builder.dotfiles(true).unstow(["a"])
+            }),
+            Status::Success
+        );
+        assert!(!fs.exists(Path::new("/home/.config")));
+    }
+
+    #[test]
+    fn stow_unfolds_directories_of_other_packages() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.config/a", "").unwrap();
+        fs.add_file("/home/dots/b/.config/b", "").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a"])),
+            Status::Success
+        );
+        assert!(fs.is_symlink(Path::new("/home/.config")));
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["b"])),
+            Status::Success
+        );
+        assert!(!fs.is_symlink(Path::new("/home/.config")));
+        assert!(fs.is_dir(Path::new("/home/.config")));
+        assert_eq!(
+            link(&fs, "/home/.config/a"),
+            Path::new("../dots/a/.config/a")
+        );
+        assert_eq!(
+            link(&fs, "/home/.config/b"),
+            Path::new("../dots/b/.config/b")
+        );
+    }
+
+    #[test]
+    fn stow_does_not_replace_files_when_not_interactive() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "bash").unwrap();
+        fs.add_file("/home/.bashrc", "mine").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a"])),
+            Status::Conflict
+        );
+        assert!(!fs.is_symlink(Path::new("/home/.bashrc")));
+        assert_eq!(fs.contents("/home/.bashrc").unwrap(), b"mine");
+    }
+
+    #[test]
+    fn run_reports_conflicts_and_applied_actions() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "").unwrap();
+        fs.add_file("/home/dots/b/.vimrc", "").unwrap();
+        fs.add_file("/home/.vimrc", "mine").unwrap();
+
+        let report = Stower::builder()
+            .fs(&fs)
+            .stow_dir(STOW_DIR)
+            .quiet(true)
+            .stow(["a", "b"])
+            .build()
+            .unwrap()
+            .run();
+
+        assert_eq!(report.status, Status::Conflict);
+        assert!(report.actions.is_empty());
+        assert!(matches!(
+            report.conflicts.as_slice(),
+            [Error::Conflict { target, .. }] if target == Path::new("/home/.vimrc")
+        ));
+
+        let report = Stower::builder()
+            .fs(&fs)
+            .stow_dir(STOW_DIR)
+            .quiet(true)
+            .stow(["a"])
+            .build()
+            .unwrap()
+            .run();
+
+        assert_eq!(report.status, Status::Success);
+        assert!(matches!(
+            report.actions.as_slice(),
+            [Action::CreateSymlink { original, destination }]
+                if original == Path::new("dots/a/.bashrc")
+                    && destination == Path::new("/home/.bashrc")
+        ));
+    }
+
+    #[test]
+    fn status_reports_missing_packages() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "").unwrap();
+
+        let report = Stower::builder()
+            .fs(&fs)
+            .stow_dir(STOW_DIR)
+            .quiet(true)
+            .status(["a", "typo"])
+            .build()
+            .unwrap()
+            .run();
+
+        assert_eq!(report.status, Status::InvalidInput);
+        assert_eq!(
+            report
+                .packages
+                .iter()
+                .map(|package| (package.package.as_path(), package.state))
+                .collect::<Vec<_>>(),
+            [
+                (Path::new("a"), PackageState::NotStowed),
+                (Path::new("typo"), PackageState::Missing),
+            ]
+        );
+    }
+
+    #[test]
+    fn simulate_does_not_change_anything() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "bash").unwrap();
+
+        let status = run(&fs, |builder| builder.stow(["a"]).simulate(true));
+
+        assert_eq!(status, Status::Success);
+        assert!(!fs.exists(Path::new("/home/.bashrc")));
+    }
+
+    #[test]
+    fn unstow_removes_the_links_of_the_package() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "").unwrap();
+        fs.add_file("/home/dots/b/.vimrc", "").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a", "b"])),
+            Status::Success
+        );
+        assert_eq!(
+            run(&fs, |builder| builder.unstow(["a"])),
+            Status::Success
+        );
+
+        assert!(!fs.is_symlink(Path::new("/home/.bashrc")));
+        assert!(fs.is_symlink(Path::new("/home/.vimrc")));
+        assert!(fs.exists(Path::new("/home/dots/a/.bashrc")));
+    }
+
+    #[test]
+    fn unstow_keeps_links_to_other_places() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "").unwrap();
+        fs.add_file("/etc/bashrc", "").unwrap();
+        fs.add_symlink("/home/.bashrc", "/etc/bashrc").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.unstow(["a"])),
+            Status::Conflict
+        );
+        assert_eq!(
+            link(&fs, "/home/.bashrc"),
+            Path::new("/etc/bashrc")
+        );
+    }
+
+    #[test]
+    fn stow_does_not_unfold_links_to_other_places() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.config/a", "").unwrap();
+        fs.add_file("/mnt/cfg/mine", "").unwrap();
+        fs.add_symlink("/home/.config", "/mnt/cfg").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a"])),
+            Status::Conflict
+        );
+        assert_eq!(link(&fs, "/home/.config"), Path::new("/mnt/cfg"));
+        assert!(!fs.exists(Path::new("/mnt/cfg/a")));
+    }
+
+    #[test]
+    fn unstow_removes_directories_it_emptied() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.config/a", "").unwrap();
+        fs.add_file("/home/dots/b/.config/b", "").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.stow(["a", "b"])),
+            Status::Success
+        );
+        assert!(fs.is_dir(Path::new("/home/.config")));
+
+        assert_eq!(
+            run(&fs, |builder| builder.unstow(["a", "b"])),
+            Status::Success
+        );
+        assert!(!fs.exists(Path::new("/home/.config")));
+    }
+
+    #[test]
+    fn unstow_keeps_directories_that_were_already_empty() {
+        let fs = MemoryFs::new();
+        fs.add_dir("/home/dots/a/.cache").unwrap();
+        fs.add_file("/home/dots/a/.bashrc", "").unwrap();
+        fs.add_dir("/home/.cache").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.unstow(["a"])),
+            Status::Success
+        );
+        assert!(fs.is_dir(Path::new("/home/.cache")));
+    }
+
+    #[test]
+    fn adopt_moves_the_target_file_into_the_package() {
+        let fs = MemoryFs::new();
+        fs.add_file("/home/dots/a/.bashrc", "package").unwrap();
+        fs.add_file("/home/.bashrc", "mine").unwrap();
+
+        assert_eq!(
+            run(&fs, |builder| builder.adopt(["a"])),
+            Status::Success
+        );
+        assert_eq!(
+            link(&fs, "/home/.bashrc"),
+            Path::new("dots/a/.bashrc")
+        );
+        assert_eq!(
+            fs.contents("/home/dots/a/.bashrc").unwrap(),
+            b"mine"
+        );
     }
 }
@@ -0,0 +1,82 @@
+use std::{collections::HashMap, env, path::Path};
+
+use crate::filesystem::Filesystem;
+
+/// Extension of the files that are rendered instead of linked. It is removed
+/// from the name of the rendered file.
+pub const TEMPLATE_EXTENSION: &str = "tmpl";
+
+/// Built-in template variables: `hostname`, `user`, `home`, `os` and `arch`.
+/// Variables that cannot be found are left out.
+pub fn builtin_variables<F: Filesystem>(fs: &F) -> HashMap<String, String> {
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+    let hostname = var("HOSTNAME").or_else(|| {
+        ["/proc/sys/kernel/hostname", "/etc/hostname"]
+            .into_iter()
+            .filter_map(|path| fs.read(Path::new(path)).ok())
+            .map(|contents| String::from_utf8_lossy(&contents).trim().to_string())
+            .find(|hostname| !hostname.is_empty())
+    });
+
+    [
+        ("hostname", hostname),
+        ("user", var("USER")),
+        ("home", var("HOME")),
+        ("os", Some(env::consts::OS.to_string())),
+        ("arch", Some(env::consts::ARCH.to_string())),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|value| (name.to_string(), value)))
+    .collect()
+}
+
+/// Replaces every `{{ name }}` in `template` with the value of the variable.
+/// On failure, returns why the template cannot be rendered.
+pub fn render(template: &str, variables: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[.. start]);
+        rest = &rest[start + 2 ..];
+
+        let Some(end) = rest.find("}}") else {
+            return Err("unclosed {{".to_string());
+        };
+        let name = rest[.. end].trim();
+        let Some(value) = variables.get(name) else {
+            return Err(format!("undefined variable \"{name}\""));
+        };
+
+        result.push_str(value);
+        rest = &rest[end + 2 ..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_replaces_variables() {
+        let variables = HashMap::from([("user".to_string(), "me".to_string())]);
+
+        for (template, expected) in [
+            ("plain", Ok("plain")),
+            ("{{ user }}", Ok("me")),
+            ("a{{user}}b {{  user  }}", Ok("ameb me")),
+            ("}} {", Ok("}} {")),
+            ("{{ host }}", Err("undefined variable \"host\"")),
+            ("{{ user", Err("unclosed {{")),
+        ] {
+            assert_eq!(
+                render(template, &variables),
+                expected.map(str::to_string).map_err(str::to_string),
+                "{template}"
+            );
+        }
+    }
+}